@@ -1,36 +1,192 @@
+use std::collections::HashMap;
 use std::fmt::Error;
 use crate::backend::Backend;
-use crate::DataFrame;
+use crate::data_frame::DataFrame;
+use crate::data_frame::{MBusDevice, MBusDeviceType, Phase, PhaseData};
+use crate::decimal::Decimal;
+use crate::events::{PowerFailureTracker, VoltageEvent, VoltageEventKind};
+use crate::meter::MeterDescription;
 use serde::Serialize;
 
 #[derive(Serialize, Debug)]
 struct ElectricityFrame {
-    t1: f64,
-    t2: f64,
+    t1: Decimal,
+    t2: Decimal,
+    received_t1: Decimal,
+    received_t2: Decimal,
+    tariff: u32,
     delivering: f64,
     receiving: f64,
+
+    l1: Option<PhaseFrame>,
+    l2: Option<PhaseFrame>,
+    l3: Option<PhaseFrame>,
+}
+
+#[derive(Serialize, Debug)]
+struct PhaseFrame {
+    voltage: Option<f64>,
+    current: Option<f64>,
+    delivering: Option<f64>,
+    receiving: Option<f64>,
+    voltage_sags: Option<u32>,
+    voltage_swells: Option<u32>,
+}
+
+impl PhaseFrame {
+    /// Absent phases are sent as null.
+    fn from_phase(phase: &PhaseData) -> Option<Self> {
+        if !phase.is_present() {
+            return None;
+        }
+
+        Some(Self {
+            voltage: phase.voltage,
+            current: phase.current,
+            delivering: phase.delivering,
+            receiving: phase.receiving,
+            voltage_sags: phase.voltage_sags,
+            voltage_swells: phase.voltage_swells,
+        })
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct PowerFailureFrame {
+    end: String,
+    /// Duration in seconds
+    duration: u64,
+}
+
+#[derive(Serialize, Debug)]
+struct VoltageEventFrame {
+    meter: String,
+    time: String,
+    phase: u8,
+    /// Either sag or swell
+    kind: &'static str,
+    count: u32,
+}
+
+impl VoltageEventFrame {
+    fn from_event(event: &VoltageEvent) -> Self {
+        Self {
+            meter: event.meter.clone(),
+            time: event.time.to_string(),
+            phase: match event.phase {
+                Phase::L1 => 1,
+                Phase::L2 => 2,
+                Phase::L3 => 3,
+            },
+            kind: match event.kind {
+                VoltageEventKind::Sag => "sag",
+                VoltageEventKind::Swell => "swell",
+            },
+            count: event.count,
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct DemandFrame {
+    /// Current 15 minute average demand in kW
+    average: Option<f64>,
+    maximum: Option<f64>,
+    maximum_time: Option<String>,
 }
 
 #[derive(Serialize, Debug)]
 struct GasFrame {
-    delivered: f64,
+    delivered: Decimal,
+}
+
+#[derive(Serialize, Debug)]
+struct MBusFrame {
+    channel: u8,
+    /// One of gas, heat, cooling, water or other
+    device_type: Option<&'static str>,
+    identifier: Option<String>,
+    time: Option<String>,
+    value: Option<Decimal>,
+    unit: Option<String>,
+}
+
+impl MBusFrame {
+    fn from_device(device: &MBusDevice) -> Self {
+        Self {
+            channel: device.channel,
+            device_type: device.device_type.map(|t| match t {
+                MBusDeviceType::Gas => "gas",
+                MBusDeviceType::Heat => "heat",
+                MBusDeviceType::Cooling => "cooling",
+                MBusDeviceType::Water => "water",
+                MBusDeviceType::Other(_) => "other",
+            }),
+            identifier: device.identifier.clone(),
+            time: device.reading.as_ref().map(|r| r.time.to_string()),
+            value: device.reading.as_ref().map(|r| r.value),
+            unit: device.reading.as_ref().map(|r| r.unit.to_string()),
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct MeterFrame {
+    manufacturer: String,
+    model: Option<&'static str>,
+    phases: Option<u8>,
+    dsmr_version: Option<u32>,
+    /// Seconds between telegrams
+    interval: Option<u64>,
+}
+
+impl MeterFrame {
+    fn from_description(description: MeterDescription) -> Self {
+        Self {
+            manufacturer: description.manufacturer,
+            model: description.model,
+            phases: description.phases,
+            dsmr_version: description.version,
+            interval: description.interval.map(|i| i.as_secs()),
+        }
+    }
 }
 
 #[derive(Serialize, Debug)]
 struct TransferFrame {
+    meter: String,
+    meter_description: MeterFrame,
     time: String,
 
     electricity: ElectricityFrame,
+    demand: DemandFrame,
     gas: GasFrame,
+    mbus: Vec<MBusFrame>,
+
+    power_failures: u32,
+    long_power_failures: u32,
+    /// Only the events that were not sent before
+    power_failure_events: Vec<PowerFailureFrame>,
+
+    /// Reasons the frame is not plausible, if it was tagged
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    implausible: Vec<String>,
 }
 
 #[derive(Serialize, Debug)]
 struct Transfer {
     frames: Vec<TransferFrame>,
+    /// Voltage events detected since the previous transfer
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    voltage_events: Vec<VoltageEventFrame>,
 }
 
+#[allow(clippy::upper_case_acronyms)]
 pub struct DSMRAPI {
     queue: Vec<DataFrame>,
+    voltage_events: Vec<VoltageEventFrame>,
+    /// Power failure events sent per meter
+    power_failures: HashMap<String, PowerFailureTracker>,
     url: String,
     authorization: String,
 }
@@ -39,6 +195,8 @@ impl DSMRAPI {
     pub fn new(url: &str, key: &str) -> Self {
         Self {
             queue: Default::default(),
+            voltage_events: Default::default(),
+            power_failures: Default::default(),
             url: format!("{}/api/v1/collect", url),
             authorization: format!("Bearer {}", key),
         }
@@ -46,26 +204,57 @@ impl DSMRAPI {
 
     fn send_queue(&mut self) {
         // drain queue, mapping into transfer frames
+        let power_failures = &mut self.power_failures;
         let frames: Vec<TransferFrame> = self.queue
             .drain(..)
             .map(|df| TransferFrame {
+                meter: df.meter_identity(),
+                meter_description: MeterFrame::from_description(df.meter_description()),
                 time: df.time.to_string(),
                 electricity: ElectricityFrame {
                     t1: df.data.electricity_delivered_t1,
                     t2: df.data.electricity_delivered_t2,
+                    received_t1: df.data.electricity_received_t1,
+                    received_t2: df.data.electricity_received_t2,
+                    tariff: df.data.tariff,
                     delivering: df.data.electricity_delivering,
-                    receiving: df.data.electricity_receiving
+                    receiving: df.data.electricity_receiving,
+                    l1: PhaseFrame::from_phase(&df.data.l1),
+                    l2: PhaseFrame::from_phase(&df.data.l2),
+                    l3: PhaseFrame::from_phase(&df.data.l3),
+                },
+                demand: DemandFrame {
+                    average: df.data.average_demand,
+                    maximum: df.data.maximum_demand.as_ref().map(|peak| peak.value.value.to_f64()),
+                    maximum_time: df.data.maximum_demand.as_ref().map(|peak| peak.time.to_string()),
                 },
                 gas: GasFrame {
                     delivered: df.data.gas_delivered,
                 },
+                mbus: df.data.mbus_devices.iter().map(MBusFrame::from_device).collect(),
+                power_failures: df.data.power_failures,
+                long_power_failures: df.data.long_power_failures,
+                power_failure_events: power_failures
+                    .entry(df.meter_identity())
+                    .or_default()
+                    .new_events(&df.data.power_failure_log)
+                    .into_iter()
+                    .map(|event| PowerFailureFrame {
+                        end: event.end.to_string(),
+                        duration: event.duration.as_secs(),
+                    })
+                    .collect(),
+                implausible: df.implausibilities().iter().map(|i| i.to_string()).collect(),
             })
             .collect();
 
-        let dto = Transfer { frames };
+        let dto = Transfer {
+            frames,
+            voltage_events: std::mem::take(&mut self.voltage_events),
+        };
 
         let client = reqwest::blocking::Client::new();
-        let res = client.post(&self.url)
+        client.post(&self.url)
             .header(reqwest::header::AUTHORIZATION, &self.authorization)
             .header(reqwest::header::CONTENT_TYPE, "application/json")
             .json(&dto)
@@ -89,4 +278,11 @@ impl Backend for DSMRAPI {
 
         Ok(())
     }
+
+    /// Events are sent with the next transfer of frames.
+    fn send_voltage_event(&mut self, event: &VoltageEvent) -> Result<(), Error> {
+        self.voltage_events.push(VoltageEventFrame::from_event(event));
+
+        Ok(())
+    }
 }
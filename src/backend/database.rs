@@ -1,18 +1,31 @@
+use std::collections::{HashMap, HashSet};
 use std::fmt::Error;
+use bytes::{BufMut, BytesMut};
 use postgres::{Client, NoTls};
+use postgres::types::{to_sql_checked, IsNull, ToSql, Type};
 use crate::backend::Backend;
-use crate::DataFrame;
+use crate::data_frame::DataFrame;
+use crate::data_frame::Phase;
+use crate::decimal::Decimal;
+use crate::events::{PowerFailureTracker, VoltageEvent, VoltageEventKind};
+use crate::validation::Implausibility;
 
 pub struct Database {
     client: Client,
+    /// Power failure events seen per meter
+    power_failures: HashMap<String, PowerFailureTracker>,
+    /// Meters of which the description was stored since the start
+    meters: HashSet<String>,
 }
 
 impl Database {
     pub fn new(url: &str) -> Self {
-        let mut client = Client::connect(url, NoTls).unwrap();
+        let client = Client::connect(url, NoTls).unwrap();
 
         Self {
             client,
+            power_failures: Default::default(),
+            meters: Default::default(),
         }
     }
 }
@@ -27,24 +40,310 @@ impl Backend for Database {
                 delivered_t1        DOUBLE PRECISION NOT NULL,
                 delivered_t2        DOUBLE PRECISION NOT NULL,
                 gas_delivered       DOUBLE PRECISION NOT NULL
-            )
+            );
+
+            ALTER TABLE dsmr_raw ADD COLUMN IF NOT EXISTS meter TEXT;
+            ALTER TABLE dsmr_raw ADD COLUMN IF NOT EXISTS receiving DOUBLE PRECISION NOT NULL DEFAULT 0;
+            ALTER TABLE dsmr_raw ADD COLUMN IF NOT EXISTS received_t1 DOUBLE PRECISION NOT NULL DEFAULT 0;
+            ALTER TABLE dsmr_raw ADD COLUMN IF NOT EXISTS received_t2 DOUBLE PRECISION NOT NULL DEFAULT 0;
+            ALTER TABLE dsmr_raw ADD COLUMN IF NOT EXISTS tariff INTEGER NOT NULL DEFAULT 0;
+            ALTER TABLE dsmr_raw ADD COLUMN IF NOT EXISTS average_demand DOUBLE PRECISION;
+            ALTER TABLE dsmr_raw ADD COLUMN IF NOT EXISTS maximum_demand DOUBLE PRECISION;
+            ALTER TABLE dsmr_raw ADD COLUMN IF NOT EXISTS maximum_demand_time TIMESTAMPTZ;
+            ALTER TABLE dsmr_raw ADD COLUMN IF NOT EXISTS power_failures INTEGER NOT NULL DEFAULT 0;
+            ALTER TABLE dsmr_raw ADD COLUMN IF NOT EXISTS long_power_failures INTEGER NOT NULL DEFAULT 0;
+
+            ALTER TABLE dsmr_raw ADD COLUMN IF NOT EXISTS voltage_l1 DOUBLE PRECISION;
+            ALTER TABLE dsmr_raw ADD COLUMN IF NOT EXISTS voltage_l2 DOUBLE PRECISION;
+            ALTER TABLE dsmr_raw ADD COLUMN IF NOT EXISTS voltage_l3 DOUBLE PRECISION;
+            ALTER TABLE dsmr_raw ADD COLUMN IF NOT EXISTS current_l1 DOUBLE PRECISION;
+            ALTER TABLE dsmr_raw ADD COLUMN IF NOT EXISTS current_l2 DOUBLE PRECISION;
+            ALTER TABLE dsmr_raw ADD COLUMN IF NOT EXISTS current_l3 DOUBLE PRECISION;
+            ALTER TABLE dsmr_raw ADD COLUMN IF NOT EXISTS delivering_l1 DOUBLE PRECISION;
+            ALTER TABLE dsmr_raw ADD COLUMN IF NOT EXISTS delivering_l2 DOUBLE PRECISION;
+            ALTER TABLE dsmr_raw ADD COLUMN IF NOT EXISTS delivering_l3 DOUBLE PRECISION;
+            ALTER TABLE dsmr_raw ADD COLUMN IF NOT EXISTS receiving_l1 DOUBLE PRECISION;
+            ALTER TABLE dsmr_raw ADD COLUMN IF NOT EXISTS receiving_l2 DOUBLE PRECISION;
+            ALTER TABLE dsmr_raw ADD COLUMN IF NOT EXISTS receiving_l3 DOUBLE PRECISION;
+
+            -- Counters are stored exactly
+            ALTER TABLE dsmr_raw ALTER COLUMN delivered_t1 TYPE NUMERIC;
+            ALTER TABLE dsmr_raw ALTER COLUMN delivered_t2 TYPE NUMERIC;
+            ALTER TABLE dsmr_raw ALTER COLUMN received_t1 TYPE NUMERIC;
+            ALTER TABLE dsmr_raw ALTER COLUMN received_t2 TYPE NUMERIC;
+            ALTER TABLE dsmr_raw ALTER COLUMN gas_delivered TYPE NUMERIC;
+
+            -- Reasons the frame is not plausible, if it was tagged
+            ALTER TABLE dsmr_raw ADD COLUMN IF NOT EXISTS implausible TEXT;
+
+            CREATE TABLE IF NOT EXISTS dsmr_meters (
+                meter               TEXT PRIMARY KEY,
+                manufacturer        TEXT NOT NULL,
+                model               TEXT,
+                phases              SMALLINT,
+                dsmr_version        INTEGER,
+                interval_seconds    INTEGER,
+                updated             TIMESTAMPTZ NOT NULL
+            );
+
+            CREATE TABLE IF NOT EXISTS dsmr_quarantine (
+                id                  SERIAL PRIMARY KEY,
+                meter               TEXT NOT NULL,
+                time                TIMESTAMPTZ NOT NULL,
+                reasons             TEXT NOT NULL,
+                delivered_t1        NUMERIC NOT NULL,
+                delivered_t2        NUMERIC NOT NULL,
+                received_t1         NUMERIC NOT NULL,
+                received_t2         NUMERIC NOT NULL,
+                gas_delivered       NUMERIC NOT NULL
+            );
+
+            CREATE TABLE IF NOT EXISTS dsmr_power_failures (
+                meter               TEXT NOT NULL,
+                end_time            TIMESTAMPTZ NOT NULL,
+                duration            INTEGER NOT NULL,
+                PRIMARY KEY (meter, end_time)
+            );
+
+            CREATE TABLE IF NOT EXISTS dsmr_mbus (
+                id                  SERIAL PRIMARY KEY,
+                meter               TEXT NOT NULL,
+                channel             SMALLINT NOT NULL,
+                device_type         INTEGER,
+                identifier          TEXT,
+                time                TIMESTAMPTZ NOT NULL,
+                value               DOUBLE PRECISION NOT NULL,
+                unit                TEXT NOT NULL,
+                UNIQUE (meter, channel, time)
+            );
+
+            ALTER TABLE dsmr_mbus ALTER COLUMN value TYPE NUMERIC;
+
+            CREATE TABLE IF NOT EXISTS dsmr_voltage_events (
+                id                  SERIAL PRIMARY KEY,
+                meter               TEXT NOT NULL,
+                time                TIMESTAMPTZ NOT NULL,
+                phase               SMALLINT NOT NULL,
+                kind                TEXT NOT NULL,
+                count               INTEGER NOT NULL
+            );
         ").unwrap();
 
         Ok(())
     }
 
     fn send(&mut self, data_frame: &DataFrame) -> Result<(), Error> {
+        // The description only changes when the meter is replaced, which also changes its identity
+        if self.meters.insert(data_frame.meter_identity()) {
+            let description = data_frame.meter_description();
+
+            self.client.execute(
+                "INSERT INTO dsmr_meters (meter, manufacturer, model, phases, dsmr_version, interval_seconds, updated)
+                VALUES ($1, $2, $3, $4, $5, $6, $7)
+                ON CONFLICT (meter) DO UPDATE SET manufacturer = $2, model = $3, phases = $4, dsmr_version = $5, interval_seconds = $6, updated = $7",
+                &[
+                    &data_frame.meter_identity(),
+                    &description.manufacturer,
+                    &description.model,
+                    &description.phases.map(|p| p as i16),
+                    &description.version.map(|v| v as i32),
+                    &description.interval.map(|i| i.as_secs() as i32),
+                    &data_frame.time,
+                ],
+            ).unwrap();
+        }
+
         self.client.execute(
-            "INSERT INTO dsmr_raw (time, delivering, delivered_t1, delivered_t2, gas_delivered) VALUES ($1, $2, $3, $4, $5)",
+            "INSERT INTO dsmr_raw (meter, time, delivering, delivered_t1, delivered_t2, receiving, received_t1, received_t2, tariff, gas_delivered,
+                average_demand, maximum_demand, maximum_demand_time, power_failures, long_power_failures, voltage_l1, voltage_l2, voltage_l3, current_l1, current_l2, current_l3,
+                delivering_l1, delivering_l2, delivering_l3, receiving_l1, receiving_l2, receiving_l3, implausible)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19, $20, $21, $22, $23, $24, $25, $26, $27, $28)",
             &[
+                &data_frame.meter_identity(),
                 &data_frame.time,
                 &data_frame.data.electricity_delivering,
                 &data_frame.data.electricity_delivered_t1,
                 &data_frame.data.electricity_delivered_t2,
-                &data_frame.data.gas_delivered
+                &data_frame.data.electricity_receiving,
+                &data_frame.data.electricity_received_t1,
+                &data_frame.data.electricity_received_t2,
+                &(data_frame.data.tariff as i32),
+                &data_frame.data.gas_delivered,
+                &data_frame.data.average_demand,
+                &data_frame.data.maximum_demand.as_ref().map(|peak| peak.value.value.to_f64()),
+                &data_frame.data.maximum_demand.as_ref().map(|peak| peak.time),
+                &(data_frame.data.power_failures as i32),
+                &(data_frame.data.long_power_failures as i32),
+                &data_frame.data.l1.voltage,
+                &data_frame.data.l2.voltage,
+                &data_frame.data.l3.voltage,
+                &data_frame.data.l1.current,
+                &data_frame.data.l2.current,
+                &data_frame.data.l3.current,
+                &data_frame.data.l1.delivering,
+                &data_frame.data.l2.delivering,
+                &data_frame.data.l3.delivering,
+                &data_frame.data.l1.receiving,
+                &data_frame.data.l2.receiving,
+                &data_frame.data.l3.receiving,
+                &Some(reasons(data_frame.implausibilities())).filter(|r| !r.is_empty()),
             ],
         ).unwrap();
 
+        // M-Bus devices are only read every 5 minutes, so most frames repeat the same reading.
+        // The unique identification of a reading is the device and the time it was read.
+        for device in &data_frame.data.mbus_devices {
+            if let Some(reading) = &device.reading {
+                self.client.execute(
+                    "INSERT INTO dsmr_mbus (meter, channel, device_type, identifier, time, value, unit)
+                    VALUES ($1, $2, $3, $4, $5, $6, $7) ON CONFLICT DO NOTHING",
+                    &[
+                        &data_frame.meter_identity(),
+                        &(device.channel as i16),
+                        &device.device_type.map(|t| t.code() as i32),
+                        &device.identifier,
+                        &reading.time,
+                        &reading.value,
+                        &reading.unit.as_str(),
+                    ],
+                ).unwrap();
+            }
+        }
+
+        // The log is repeated in every frame, only store events we have not seen yet.
+        // Events from before a restart are ignored by the primary key.
+        let meter = data_frame.meter_identity();
+        let power_failures = self.power_failures.entry(meter.clone()).or_default();
+        for event in power_failures.new_events(&data_frame.data.power_failure_log) {
+            self.client.execute(
+                "INSERT INTO dsmr_power_failures (meter, end_time, duration) VALUES ($1, $2, $3) ON CONFLICT DO NOTHING",
+                &[
+                    &meter,
+                    &event.end,
+                    &(event.duration.as_secs() as i32),
+                ],
+            ).unwrap();
+        }
+
         Ok(())
     }
+
+    fn quarantine(&mut self, data_frame: &DataFrame, implausibilities: &[Implausibility]) -> Result<(), Error> {
+        self.client.execute(
+            "INSERT INTO dsmr_quarantine (meter, time, reasons, delivered_t1, delivered_t2, received_t1, received_t2, gas_delivered)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+            &[
+                &data_frame.meter_identity(),
+                &data_frame.time,
+                &reasons(implausibilities),
+                &data_frame.data.electricity_delivered_t1,
+                &data_frame.data.electricity_delivered_t2,
+                &data_frame.data.electricity_received_t1,
+                &data_frame.data.electricity_received_t2,
+                &data_frame.data.gas_delivered,
+            ],
+        ).unwrap();
+
+        Ok(())
+    }
+
+    fn send_voltage_event(&mut self, event: &VoltageEvent) -> Result<(), Error> {
+        let phase: i16 = match event.phase {
+            Phase::L1 => 1,
+            Phase::L2 => 2,
+            Phase::L3 => 3,
+        };
+        let kind = match event.kind {
+            VoltageEventKind::Sag => "sag",
+            VoltageEventKind::Swell => "swell",
+        };
+
+        self.client.execute(
+            "INSERT INTO dsmr_voltage_events (meter, time, phase, kind, count) VALUES ($1, $2, $3, $4, $5)",
+            &[
+                &event.meter,
+                &event.time,
+                &phase,
+                &kind,
+                &(event.count as i32),
+            ],
+        ).unwrap();
+
+        Ok(())
+    }
+}
+
+fn reasons(implausibilities: &[Implausibility]) -> String {
+    implausibilities.iter().map(|i| i.to_string()).collect::<Vec<_>>().join("; ")
+}
+
+/// Decimals are written as NUMERIC, which is a list of base 10000 digits with the weight of the
+/// first digit, a sign and the number of decimals.
+impl ToSql for Decimal {
+    fn to_sql(&self, _: &Type, out: &mut BytesMut) -> Result<IsNull, Box<dyn std::error::Error + Sync + Send>> {
+        let scale = self.scale() as usize;
+        let digits = format!("{:0>width$}", self.mantissa().unsigned_abs(), width = scale + 1);
+        let (integer, fraction) = digits.split_at(digits.len() - scale);
+
+        // Align both parts to groups of 4 decimal digits around the point
+        let integer = format!("{:0>width$}", integer, width = integer.len().div_ceil(4) * 4);
+        let fraction = format!("{:0<width$}", fraction, width = fraction.len().div_ceil(4) * 4);
+        let mut groups: Vec<i16> = integer.as_bytes()
+            .chunks(4)
+            .chain(fraction.as_bytes().chunks(4))
+            .map(|group| group.iter().fold(0, |n, d| n * 10 + (d - b'0') as i16))
+            .collect();
+        let mut weight = (integer.len() / 4) as i16 - 1;
+
+        while groups.first() == Some(&0) {
+            groups.remove(0);
+            weight -= 1;
+        }
+        while groups.last() == Some(&0) {
+            groups.pop();
+        }
+        if groups.is_empty() {
+            weight = 0;
+        }
+
+        out.put_i16(groups.len() as i16);
+        out.put_i16(weight);
+        out.put_u16(if self.mantissa() < 0 { 0x4000 } else { 0 });
+        out.put_u16(scale as u16);
+        for group in groups {
+            out.put_i16(group);
+        }
+
+        Ok(IsNull::No)
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        *ty == Type::NUMERIC
+    }
+
+    to_sql_checked!();
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::BytesMut;
+    use postgres::types::{ToSql, Type};
+    use crate::decimal::Decimal;
+
+    fn numeric(value: &str) -> Vec<u8> {
+        let mut out = BytesMut::new();
+        Decimal::parse(value).unwrap().to_sql(&Type::NUMERIC, &mut out).unwrap();
+        out.to_vec()
+    }
+
+    #[test]
+    fn decimal_as_numeric() {
+        // 2 digits, weight 0, positive, scale 3: 1382 and 5700
+        assert_eq!(numeric("001382.570"), vec![0, 2, 0, 0, 0, 0, 0, 3, 0x05, 0x66, 0x16, 0x44]);
+        // 1 digit, weight -1, scale 3: 0.0050
+        assert_eq!(numeric("0.005"), vec![0, 1, 0xff, 0xff, 0, 0, 0, 3, 0, 50]);
+        // 1 digit with weight 1, the trailing zero digit is left out
+        assert_eq!(numeric("10000"), vec![0, 1, 0, 1, 0, 0, 0, 0, 0, 1]);
+        assert_eq!(numeric("0.000"), vec![0, 0, 0, 0, 0, 0, 0, 3]);
+    }
 }
@@ -1,5 +1,7 @@
 use std::fmt::Error;
-use crate::DataFrame;
+use crate::data_frame::DataFrame;
+use crate::events::VoltageEvent;
+use crate::validation::Implausibility;
 
 #[cfg(feature = "api")]
 mod api;
@@ -16,4 +18,15 @@ pub use database::*;
 pub trait Backend {
     fn init(&mut self) -> Result<(), Error>;
     fn send(&mut self, data_frame: &DataFrame) -> Result<(), Error>;
+
+    /// Store a detected voltage event. Backends that do not store events can ignore them.
+    fn send_voltage_event(&mut self, _event: &VoltageEvent) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Keep a frame that is not plausible apart from the other frames, to be reviewed.
+    /// Backends that can not quarantine frames drop them.
+    fn quarantine(&mut self, _data_frame: &DataFrame, _implausibilities: &[Implausibility]) -> Result<(), Error> {
+        Ok(())
+    }
 }
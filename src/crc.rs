@@ -0,0 +1,37 @@
+/// Calculate the CRC16 of a telegram as specified by DSMR 5.
+///
+/// This is CRC16/ARC: reversed polynomial 0xA001, starting at 0 and without a final XOR. The
+/// checksum covers every byte from the leading `/` up to and including the `!`.
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0;
+
+    for byte in data {
+        crc ^= *byte as u16;
+
+        for _ in 0..8 {
+            if crc & 1 == 1 {
+                crc = (crc >> 1) ^ 0xA001;
+            } else {
+                crc >>= 1;
+            }
+        }
+    }
+
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::crc::crc16;
+
+    #[test]
+    fn check_value() {
+        // Standard check value for CRC16/ARC
+        assert_eq!(crc16(b"123456789"), 0xBB3D);
+    }
+
+    #[test]
+    fn empty_input() {
+        assert_eq!(crc16(b""), 0);
+    }
+}
@@ -1,4 +1,10 @@
-use chrono::{DateTime, Local};
+use std::time::Duration;
+use chrono::{DateTime, FixedOffset, Local};
+use crate::decimal::Decimal;
+use crate::meter::MeterDescription;
+use crate::obis::ObisCode;
+use crate::parser::ParseError;
+use crate::validation::Implausibility;
 
 #[derive(Debug)]
 pub struct RawFrame {
@@ -6,7 +12,7 @@ pub struct RawFrame {
 }
 
 impl RawFrame {
-    pub(crate) fn new(data: String) -> Self {
+    pub fn new(data: String) -> Self {
         Self { data }
     }
 
@@ -14,6 +20,10 @@ impl RawFrame {
         self.data.len()
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
     pub fn get_data(&self) -> &str {
         self.data.as_str()
     }
@@ -22,72 +32,559 @@ impl RawFrame {
 #[derive(Debug, PartialEq, Clone)]
 pub enum Object {
     Version(u32), // ?, 1-3:0.2.8.255
-    Time(DateTime<Local>), // 0-0:1.0.0.255
+    Time(DateTime<FixedOffset>), // 0-0:1.0.0.255
+    EquipmentIdentifier(String), // 0-0:96.1.1.255   Sn (n=0..96), tag 9
+    TextMessage(String), // 0-0:96.13.0.255   Sn (n=0..2048), tag 9
+
+    ElectricityDeliveredT1(Measurement), // 1-0:1.8.1.255   F9(3,3), tag 6
+    ElectricityDeliveredT2(Measurement), // 1-0:1.8.2.255
+
+    ElectricityReceivedT1(Measurement), // 1-0:2.8.1.255   F9(3,3), tag 6
+    ElectricityReceivedT2(Measurement), // 1-0:2.8.2.255
+
+    TariffIndicator(u32), // 0-0:96.14.0.255   S4, tag 9
+
+    ElectricityDelivering(Measurement), // 1-0:1.7.0.255   F5(3,3), tag 18
+    ElectricityReceiving(Measurement), // 1-0:2.7.0.255
+
+    Voltage(Phase, Measurement), // 1-0:32.7.0.255 (L1), 52.7.0 (L2), 72.7.0 (L3)   F4(1,1), tag 18
+    Current(Phase, Measurement), // 1-0:31.7.0.255 (L1), 51.7.0 (L2), 71.7.0 (L3)   F3(0,0), tag 18
+    PhaseDelivering(Phase, Measurement), // 1-0:21.7.0.255 (L1), 41.7.0 (L2), 61.7.0 (L3)   F5(3,3), tag 18
+    PhaseReceiving(Phase, Measurement), // 1-0:22.7.0.255 (L1), 42.7.0 (L2), 62.7.0 (L3)
+    VoltageSags(Phase, u32), // 1-0:32.32.0.255 (L1), 52.32.0 (L2), 72.32.0 (L3)   F5(0,0), tag 18
+    VoltageSwells(Phase, u32), // 1-0:32.36.0.255 (L1), 52.36.0 (L2), 72.36.0 (L3)
+
+    PowerFailures(u32), // 0-0:96.7.21.255   F5(0,0), tag 18
+    LongPowerFailures(u32), // 0-0:96.7.9.255
+    PowerFailureLog(Vec<PowerFailureEvent>), // 1-0:99.97.0.255   TST, F10(0,0), tag 6
+
+    EmucsVersion(u32), // 0-0:96.1.4.255   e-MUCS (Belgium)
+    AverageDemand(Measurement), // 1-0:1.4.0.255   current 15 minute average demand
+    MaximumDemand(DemandPeak), // 1-0:1.6.0.255   peak of the running month
+    MaximumDemandHistory(Vec<MonthlyDemandPeak>), // 0-0:98.1.0.255   peaks of the last 13 months
+
+    MBusDeviceType(u8, MBusDeviceType), // 0-n:24.1.0.255   F3(0,0), tag 17
+    MBusEquipmentIdentifier(u8, String), // 0-n:96.1.0.255 (0-n:96.1.1.255 on e-MUCS)   Sn (n=0..96), tag 9
+    MBusReading(u8, MBusReading), // 0-n:24.2.1.255   TST, F8(2,2)/F8(3,3), tag 4
+
+    Custom(ObisCode, CustomValue), // parsed by an ObjectHandler registered on the parser
+    Unknown(ObisCode, String),
+}
+
+/// Value of an object parsed by an [ObjectHandler](crate::parser::ObjectHandler).
+#[derive(Debug, PartialEq, Clone)]
+pub enum CustomValue {
+    Integer(u32),
+    Measurement(Measurement),
+    Text(String),
+    Time(DateTime<FixedOffset>),
+}
+
+/// An object with the OBIS reference it was sent with.
+#[derive(Debug, PartialEq, Clone)]
+pub struct TelegramObject {
+    pub obis: ObisCode,
+    pub object: Object,
+}
+
+impl TelegramObject {
+    pub fn new(obis: ObisCode, object: Object) -> Self {
+        Self { obis, object }
+    }
+}
+
+/// Unit of a value in a telegram.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Unit {
+    KWh,
+    Wh,
+    KW,
+    W,
+    V,
+    A,
+    M3,
+    GJ,
+    S,
+}
+
+impl Unit {
+    /// Parse a unit as written in a telegram, e.g. `kWh`.
+    pub fn parse(unit: &str) -> Option<Self> {
+        let unit = match unit {
+            "kWh" => Unit::KWh,
+            "Wh" => Unit::Wh,
+            "kW" => Unit::KW,
+            "W" => Unit::W,
+            "V" => Unit::V,
+            "A" => Unit::A,
+            "m3" => Unit::M3,
+            "GJ" => Unit::GJ,
+            "s" => Unit::S,
+            _ => return None,
+        };
+
+        Some(unit)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Unit::KWh => "kWh",
+            Unit::Wh => "Wh",
+            Unit::KW => "kW",
+            Unit::W => "W",
+            Unit::V => "V",
+            Unit::A => "A",
+            Unit::M3 => "m3",
+            Unit::GJ => "GJ",
+            Unit::S => "s",
+        }
+    }
+}
+
+impl std::fmt::Display for Unit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A value with the unit it was sent in.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Measurement {
+    pub value: Decimal,
+    pub unit: Unit,
+}
+
+impl Measurement {
+    pub fn new(value: Decimal, unit: Unit) -> Self {
+        Self { value, unit }
+    }
+
+    /// Convert to the unit used in `DataFrameData`, which is kWh for energy and kW for power.
+    /// Returns `None` when the value has too many decimals to be converted exactly.
+    pub fn normalized(&self) -> Option<Self> {
+        match self.unit {
+            Unit::Wh => Some(Self::new(self.value.checked_shift(3)?, Unit::KWh)),
+            Unit::W => Some(Self::new(self.value.checked_shift(3)?, Unit::KW)),
+            _ => Some(*self),
+        }
+    }
+}
+
+/// A long power failure, as recorded in the power failure event log of the meter.
+#[derive(Debug, PartialEq, Clone)]
+pub struct PowerFailureEvent {
+    /// Time at which the power came back
+    pub end: DateTime<FixedOffset>,
+    pub duration: Duration,
+}
+
+/// Highest 15 minute average demand within a period.
+#[derive(Debug, PartialEq, Clone)]
+pub struct DemandPeak {
+    pub time: DateTime<FixedOffset>,
+    pub value: Measurement,
+}
+
+impl DemandPeak {
+    /// Convert to the unit used in `DataFrameData`, see `Measurement::normalized`.
+    pub fn normalized(&self) -> Option<Self> {
+        Some(Self {
+            time: self.time,
+            value: self.value.normalized()?,
+        })
+    }
+}
+
+/// Demand peak of a past month. Meters send entries without a valid time for months in which
+/// they were not active yet.
+#[derive(Debug, PartialEq, Clone)]
+pub struct MonthlyDemandPeak {
+    /// Start of the month
+    pub month: Option<DateTime<FixedOffset>>,
+    pub time: Option<DateTime<FixedOffset>>,
+    pub value: Measurement,
+}
+
+impl MonthlyDemandPeak {
+    /// Convert to the unit used in `DataFrameData`, see `Measurement::normalized`.
+    pub fn normalized(&self) -> Option<Self> {
+        Some(Self {
+            month: self.month,
+            time: self.time,
+            value: self.value.normalized()?,
+        })
+    }
+}
+
+/// Type of an M-Bus device, as defined in EN 13757-3.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum MBusDeviceType {
+    Gas,
+    Heat,
+    Cooling,
+    Water,
+    Other(u32),
+}
+
+impl MBusDeviceType {
+    pub fn from_code(code: u32) -> Self {
+        match code {
+            3 => MBusDeviceType::Gas,
+            4 | 12 => MBusDeviceType::Heat,
+            10 | 11 => MBusDeviceType::Cooling,
+            7 => MBusDeviceType::Water,
+            _ => MBusDeviceType::Other(code),
+        }
+    }
+
+    /// Device type code, the first one for types that have several.
+    pub fn code(&self) -> u32 {
+        match self {
+            MBusDeviceType::Gas => 3,
+            MBusDeviceType::Heat => 4,
+            MBusDeviceType::Cooling => 10,
+            MBusDeviceType::Water => 7,
+            MBusDeviceType::Other(code) => *code,
+        }
+    }
+}
+
+/// Last reading of the counter of an M-Bus device.
+#[derive(Debug, PartialEq, Clone)]
+pub struct MBusReading {
+    /// Time at which the device was read by the meter
+    pub time: DateTime<FixedOffset>,
+    pub value: Decimal,
+    pub unit: Unit,
+}
 
-    ElectricityDeliveredT1(f64), // 1-0:1.8.1.255   F9(3,3), tag 6
-    ElectricityDeliveredT2(f64), // 1-0:1.8.2.255
+impl MBusReading {
+    /// Convert to the unit used in `DataFrameData`, see `Measurement::normalized`.
+    pub fn normalized(&self) -> Option<Self> {
+        let measurement = Measurement::new(self.value, self.unit).normalized()?;
 
-    ElectricityDelivering(f64), // 1-0:1.7.0.255   F5(3,3), tag 18
-    ElectricityReceiving(f64), // 1-0:2.7.0.255
+        Some(Self {
+            time: self.time,
+            value: measurement.value,
+            unit: measurement.unit,
+        })
+    }
+}
 
-    GasDelivered(DateTime<Local>, f64),
+/// A device connected to the meter over M-Bus, such as a gas or water meter.
+#[derive(Debug, PartialEq, Clone)]
+pub struct MBusDevice {
+    /// M-Bus channel, 1 to 4
+    pub channel: u8,
+    pub device_type: Option<MBusDeviceType>,
+    pub identifier: Option<String>,
+    pub reading: Option<MBusReading>,
+}
 
-    Unknown(String, String),
+impl MBusDevice {
+    fn new(channel: u8) -> Self {
+        Self {
+            channel,
+            device_type: None,
+            identifier: None,
+            reading: None,
+        }
+    }
+
+    /// Whether this is a gas meter. Devices without a type are assumed to be a gas meter when
+    /// they report in m3, as older meters only supported gas.
+    pub fn is_gas(&self) -> bool {
+        match self.device_type {
+            Some(device_type) => device_type == MBusDeviceType::Gas,
+            None => self.reading.as_ref().is_some_and(|r| r.unit == Unit::M3),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Phase {
+    L1,
+    L2,
+    L3,
+}
+
+impl Phase {
+    pub const ALL: [Phase; 3] = [Phase::L1, Phase::L2, Phase::L3];
 }
 
 #[derive(Debug, Clone)]
 pub struct DataFrame {
     prefix: String,
+    baud_rate: char,
     identifier: String,
-    checksum: u16,
+    equipment_identifier: Option<String>,
+    checksum: Option<u16>,
+    calculated_checksum: u16,
+    skipped: Vec<ParseError>,
+    objects: Vec<TelegramObject>,
+    implausibilities: Vec<Implausibility>,
 
     pub version: u32,
-    pub time: DateTime<Local>,
+    pub time: DateTime<FixedOffset>,
     pub data: DataFrameData,
 }
 
 #[derive(Debug, Default, Clone)]
 pub struct DataFrameData {
-    pub electricity_delivered_t1: f64,
-    pub electricity_delivered_t2: f64,
+    /// Energy counters in kWh, exactly as read from the meter
+    pub electricity_delivered_t1: Decimal,
+    pub electricity_delivered_t2: Decimal,
+
+    pub electricity_received_t1: Decimal,
+    pub electricity_received_t2: Decimal,
+
+    /// Active tariff, 1 for T1 (low) and 2 for T2 (normal)
+    pub tariff: u32,
 
     pub electricity_delivering: f64,
     pub electricity_receiving: f64,
 
-    pub gas_delivered: f64,
+    /// Per-phase values. Single-phase connections only report L1.
+    pub l1: PhaseData,
+    pub l2: PhaseData,
+    pub l3: PhaseData,
+
+    /// Number of power failures in any phase
+    pub power_failures: u32,
+    /// Number of long power failures in any phase
+    pub long_power_failures: u32,
+    /// Most recent long power failures, oldest first. The meter keeps a limited number of events.
+    pub power_failure_log: Vec<PowerFailureEvent>,
+
+    /// Version of the e-MUCS specification, only sent by Belgian meters
+    pub emucs_version: Option<u32>,
+    /// Current 15 minute average demand in kW, used for the Belgian capacity tariff
+    pub average_demand: Option<f64>,
+    /// Highest 15 minute average demand of the running month
+    pub maximum_demand: Option<DemandPeak>,
+    /// Highest 15 minute average demand of the previous months, newest first
+    pub maximum_demand_history: Vec<MonthlyDemandPeak>,
+
+    /// Text message from the grid operator, if any
+    pub text_message: Option<String>,
+    /// Devices connected over M-Bus, ordered by channel
+    pub mbus_devices: Vec<MBusDevice>,
+
+    /// Reading of the first gas meter in m3
+    pub gas_delivered: Decimal,
+}
+
+/// Instantaneous values of a single phase. Values are `None` when the meter did not report them.
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct PhaseData {
+    /// Voltage in V
+    pub voltage: Option<f64>,
+    /// Current in A
+    pub current: Option<f64>,
+    /// Power delivered to the client in kW
+    pub delivering: Option<f64>,
+    /// Power received from the client in kW
+    pub receiving: Option<f64>,
+    /// Number of voltage sags
+    pub voltage_sags: Option<u32>,
+    /// Number of voltage swells
+    pub voltage_swells: Option<u32>,
+}
+
+impl PhaseData {
+    /// Whether the meter reported any value for this phase.
+    pub fn is_present(&self) -> bool {
+        self.voltage.is_some() || self.current.is_some() || self.delivering.is_some() || self.receiving.is_some()
+            || self.voltage_sags.is_some() || self.voltage_swells.is_some()
+    }
+}
+
+impl DataFrameData {
+    pub fn phase(&self, phase: Phase) -> &PhaseData {
+        match phase {
+            Phase::L1 => &self.l1,
+            Phase::L2 => &self.l2,
+            Phase::L3 => &self.l3,
+        }
+    }
+
+    fn mbus_device_mut(&mut self, channel: u8) -> &mut MBusDevice {
+        let index = match self.mbus_devices.binary_search_by_key(&channel, |d| d.channel) {
+            Ok(index) => index,
+            Err(index) => {
+                self.mbus_devices.insert(index, MBusDevice::new(channel));
+                index
+            }
+        };
+
+        &mut self.mbus_devices[index]
+    }
+
+    fn phase_mut(&mut self, phase: Phase) -> &mut PhaseData {
+        match phase {
+            Phase::L1 => &mut self.l1,
+            Phase::L2 => &mut self.l2,
+            Phase::L3 => &mut self.l3,
+        }
+    }
 }
 
 impl DataFrame {
-    pub fn new(prefix: String, identifier: String, objects: Vec<Object>, checksum: u16) -> Self {
+    pub fn new(prefix: String, identifier: String, objects: Vec<TelegramObject>, checksum: Option<u16>, calculated_checksum: u16) -> Self {
         let mut data = DataFrameData::default();
 
         let mut version: u32 = 0;
-        let mut time = Local::now();
+        let mut time: DateTime<FixedOffset> = Local::now().into();
+        let mut equipment_identifier = None;
+
+        // Values with too many decimals to be converted are left out
+        let value = |v: &Measurement| v.normalized().map(|v| v.value);
 
-        for object in objects.iter() {
+        for TelegramObject { object, .. } in objects.iter() {
             match object {
                 Object::Version(v) => version = *v,
-                Object::Time(t) => time = t.clone(),
-                Object::ElectricityDeliveredT1(v) => data.electricity_delivered_t1 = *v,
-                Object::ElectricityDeliveredT2(v) => data.electricity_delivered_t2 = *v,
-                Object::ElectricityDelivering(v) => data.electricity_delivering = *v,
-                Object::ElectricityReceiving(v) => data.electricity_receiving = *v,
-                Object::GasDelivered(_, v) => data.gas_delivered = *v,
-                Object::Unknown(_, _) => {}
+                Object::Time(t) => time = *t,
+                Object::EquipmentIdentifier(v) => equipment_identifier = Some(v.clone()),
+                Object::TextMessage(v) => data.text_message = Some(v.clone()).filter(|v| !v.is_empty()),
+                Object::EmucsVersion(v) => data.emucs_version = Some(*v),
+                Object::AverageDemand(v) => data.average_demand = value(v).map(Decimal::to_f64),
+                Object::MaximumDemand(v) => data.maximum_demand = v.normalized(),
+                Object::MaximumDemandHistory(v) => data.maximum_demand_history = v.iter().filter_map(MonthlyDemandPeak::normalized).collect(),
+                Object::MBusDeviceType(c, v) => data.mbus_device_mut(*c).device_type = Some(*v),
+                Object::MBusEquipmentIdentifier(c, v) => data.mbus_device_mut(*c).identifier = Some(v.clone()),
+                Object::MBusReading(c, v) => data.mbus_device_mut(*c).reading = v.normalized(),
+                Object::ElectricityDeliveredT1(v) => data.electricity_delivered_t1 = value(v).unwrap_or_default(),
+                Object::ElectricityDeliveredT2(v) => data.electricity_delivered_t2 = value(v).unwrap_or_default(),
+                Object::ElectricityReceivedT1(v) => data.electricity_received_t1 = value(v).unwrap_or_default(),
+                Object::ElectricityReceivedT2(v) => data.electricity_received_t2 = value(v).unwrap_or_default(),
+                Object::TariffIndicator(v) => data.tariff = *v,
+                Object::ElectricityDelivering(v) => data.electricity_delivering = value(v).map_or(0.0, Decimal::to_f64),
+                Object::ElectricityReceiving(v) => data.electricity_receiving = value(v).map_or(0.0, Decimal::to_f64),
+                Object::Voltage(p, v) => data.phase_mut(*p).voltage = Some(v.value.to_f64()),
+                Object::Current(p, v) => data.phase_mut(*p).current = Some(v.value.to_f64()),
+                Object::PhaseDelivering(p, v) => data.phase_mut(*p).delivering = value(v).map(Decimal::to_f64),
+                Object::PhaseReceiving(p, v) => data.phase_mut(*p).receiving = value(v).map(Decimal::to_f64),
+                Object::VoltageSags(p, v) => data.phase_mut(*p).voltage_sags = Some(*v),
+                Object::VoltageSwells(p, v) => data.phase_mut(*p).voltage_swells = Some(*v),
+                Object::PowerFailures(v) => data.power_failures = *v,
+                Object::LongPowerFailures(v) => data.long_power_failures = *v,
+                Object::PowerFailureLog(events) => data.power_failure_log = events.clone(),
+                Object::Custom(_, _) | Object::Unknown(_, _) => {}
             }
         }
 
+        data.gas_delivered = data.mbus_devices
+            .iter()
+            .find(|d| d.is_gas())
+            .and_then(|d| d.reading.as_ref())
+            .map_or(Decimal::ZERO, |r| r.value);
+
         Self {
             prefix,
+            baud_rate: '5',
             identifier,
+            equipment_identifier,
             data,
             checksum,
+            calculated_checksum,
+            skipped: Vec::new(),
+            objects,
+            implausibilities: Vec::new(),
             time,
             version,
         }
     }
 
+    /// Identity of the meter, to key data on. This is the equipment identifier, or the
+    /// header identification for meters that do not send one.
+    pub fn meter_identity(&self) -> String {
+        match &self.equipment_identifier {
+            Some(id) => id.clone(),
+            None => format!("{}{}", self.prefix, self.identifier),
+        }
+    }
+
+    /// Manufacturer, model and capabilities of the meter.
+    pub fn meter_description(&self) -> MeterDescription {
+        let has_values = |p: &PhaseData| p.voltage.is_some() || p.current.is_some() || p.delivering.is_some();
+        let phases = match (has_values(&self.data.l1), has_values(&self.data.l2) || has_values(&self.data.l3)) {
+            (_, true) => Some(3),
+            (true, false) => Some(1),
+            (false, false) => None,
+        };
+
+        MeterDescription::identify(&self.prefix, &self.identifier, self.version, phases, self.data.emucs_version.is_some())
+    }
+
+    /// Manufacturer prefix of the header, e.g. "ISK".
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    /// Baud rate character of the header. This is always '5' since DSMR 4, older meters may
+    /// send another character.
+    pub fn baud_rate(&self) -> char {
+        self.baud_rate
+    }
+
+    /// Identification of the meter in the header, after the prefix and baud rate character.
+    pub fn identifier(&self) -> &str {
+        &self.identifier
+    }
+
+    /// All objects of the telegram in the order they were sent, including unknown objects.
+    pub fn objects(&self) -> &[TelegramObject] {
+        &self.objects
+    }
+
+    /// The first object with the given OBIS reference.
+    pub fn object(&self, obis: ObisCode) -> Option<&Object> {
+        self.objects
+            .iter()
+            .find(|o| o.obis == obis)
+            .map(|o| &o.object)
+    }
+
+    pub(crate) fn with_baud_rate(mut self, baud_rate: char) -> Self {
+        self.baud_rate = baud_rate;
+        self
+    }
+
+    pub(crate) fn with_skipped(mut self, skipped: Vec<ParseError>) -> Self {
+        self.skipped = skipped;
+        self
+    }
+
+    /// Objects that could not be parsed and were left out, when parsing in lenient mode.
+    pub fn skipped(&self) -> &[ParseError] {
+        &self.skipped
+    }
+
+    /// Tag the frame with the reasons it is not plausible.
+    pub fn with_implausibilities(mut self, implausibilities: Vec<Implausibility>) -> Self {
+        self.implausibilities = implausibilities;
+        self
+    }
+
+    /// Reasons the frame is not plausible compared to the previous frames, when it was tagged
+    /// by the plausibility check.
+    pub fn implausibilities(&self) -> &[Implausibility] {
+        &self.implausibilities
+    }
+
+    /// Checksum as sent by the meter in the footer. Meters before DSMR 4 do not send a checksum.
+    pub fn checksum(&self) -> Option<u16> {
+        self.checksum
+    }
+
+    /// Checksum calculated over the received telegram.
+    pub fn calculated_checksum(&self) -> u16 {
+        self.calculated_checksum
+    }
+
+    /// Whether the telegram was received without corruption. Telegrams without a checksum
+    /// can not be checked, and are assumed to be valid.
     pub fn is_valid(&self) -> bool {
-        true // no validation
+        self.checksum.is_none_or(|checksum| checksum == self.calculated_checksum)
     }
 }
@@ -0,0 +1,220 @@
+use std::cmp::Ordering;
+use std::ops::{Add, Sub};
+
+/// Exact decimal number, stored as an integer scaled by a power of ten.
+///
+/// Values keep the precision the meter sent them with, so `001382.570` is stored as 1382570
+/// with scale 3 and is formatted as `1382.570` again. Sums and differences are exact.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Decimal {
+    mantissa: i64,
+    scale: u32,
+}
+
+/// Largest scale, so that rescaled mantissas always fit an i128.
+const MAX_SCALE: u32 = 18;
+/// Most decimals accepted when parsing. Meters send at most 3, this leaves room for conversions.
+const MAX_PARSED_SCALE: usize = 9;
+
+impl Decimal {
+    pub const ZERO: Decimal = Decimal { mantissa: 0, scale: 0 };
+
+    /// Create the number `mantissa * 10^-scale`.
+    pub fn new(mantissa: i64, scale: u32) -> Self {
+        assert!(scale <= MAX_SCALE, "scale {} is larger than {}", scale, MAX_SCALE);
+
+        Self { mantissa, scale }
+    }
+
+    /// Parse a number as written in a telegram, e.g. `001382.570`. Signs and exponents are
+    /// not allowed.
+    pub fn parse(input: &str) -> Option<Self> {
+        let (integer, fraction) = match input.split_once('.') {
+            Some((integer, fraction)) => (integer, fraction),
+            None => (input, ""),
+        };
+
+        let is_digits = |s: &str| s.bytes().all(|b| b.is_ascii_digit());
+        if integer.is_empty() || !is_digits(integer) || !is_digits(fraction) || fraction.len() > MAX_PARSED_SCALE {
+            return None;
+        }
+
+        let mantissa = format!("{}{}", integer, fraction).parse().ok()?;
+
+        Some(Self::new(mantissa, fraction.len() as u32))
+    }
+
+    /// The unscaled integer value.
+    pub fn mantissa(&self) -> i64 {
+        self.mantissa
+    }
+
+    /// Number of decimals.
+    pub fn scale(&self) -> u32 {
+        self.scale
+    }
+
+    /// Closest floating point value, for values where exactness does not matter.
+    pub fn to_f64(self) -> f64 {
+        self.mantissa as f64 / 10f64.powi(self.scale as i32)
+    }
+
+    /// Divide by `10^n` exactly by increasing the scale, e.g. to convert Wh into kWh. Returns
+    /// `None` when the scale would become too large.
+    pub fn checked_shift(&self, n: u32) -> Option<Self> {
+        let scale = self.scale.checked_add(n).filter(|scale| *scale <= MAX_SCALE)?;
+
+        Some(Self::new(self.mantissa, scale))
+    }
+
+    /// Exact sum, or `None` when it does not fit.
+    pub fn checked_add(self, other: Self) -> Option<Self> {
+        let scale = self.scale.max(other.scale);
+
+        Self::from_rescaled(self.rescaled(scale) + other.rescaled(scale), scale)
+    }
+
+    /// Exact difference, or `None` when it does not fit.
+    pub fn checked_sub(self, other: Self) -> Option<Self> {
+        let scale = self.scale.max(other.scale);
+
+        Self::from_rescaled(self.rescaled(scale) - other.rescaled(scale), scale)
+    }
+
+    /// Mantissa at a larger scale. Comparisons are done in i128 so they can not overflow.
+    fn rescaled(&self, scale: u32) -> i128 {
+        self.mantissa as i128 * 10i128.pow(scale - self.scale)
+    }
+
+    fn from_rescaled(mantissa: i128, scale: u32) -> Option<Self> {
+        let mantissa = i64::try_from(mantissa).ok()?;
+
+        Some(Self::new(mantissa, scale))
+    }
+}
+
+impl PartialEq for Decimal {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Decimal {}
+
+impl PartialOrd for Decimal {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Decimal {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let scale = self.scale.max(other.scale);
+
+        self.rescaled(scale).cmp(&other.rescaled(scale))
+    }
+}
+
+/// Panics on overflow, use `checked_add` for values read from a meter.
+impl Add for Decimal {
+    type Output = Decimal;
+
+    fn add(self, other: Self) -> Self {
+        self.checked_add(other).expect("decimal overflow")
+    }
+}
+
+/// Panics on overflow, use `checked_sub` for values read from a meter.
+impl Sub for Decimal {
+    type Output = Decimal;
+
+    fn sub(self, other: Self) -> Self {
+        self.checked_sub(other).expect("decimal overflow")
+    }
+}
+
+impl std::fmt::Display for Decimal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let sign = if self.mantissa < 0 { "-" } else { "" };
+        let digits = format!("{:0width$}", self.mantissa.unsigned_abs(), width = self.scale as usize + 1);
+        let (integer, fraction) = digits.split_at(digits.len() - self.scale as usize);
+
+        if fraction.is_empty() {
+            write!(f, "{}{}", sign, integer)
+        } else {
+            write!(f, "{}{}.{}", sign, integer, fraction)
+        }
+    }
+}
+
+/// Serialized as a JSON number with exactly the digits of the meter, e.g. 1382.570.
+#[cfg(feature = "serde_json")]
+impl serde::Serialize for Decimal {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let number: serde_json::Number = self.to_string().parse().map_err(serde::ser::Error::custom)?;
+
+        number.serialize(serializer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::decimal::Decimal;
+
+    #[test]
+    fn parse_keeps_precision() {
+        let value = Decimal::parse("001382.570").unwrap();
+
+        assert_eq!(value.mantissa(), 1382570);
+        assert_eq!(value.scale(), 3);
+        assert_eq!(value.to_string(), "1382.570");
+        assert_eq!(Decimal::parse("42").unwrap().to_string(), "42");
+        assert_eq!(Decimal::parse("0.005").unwrap().to_string(), "0.005");
+    }
+
+    #[test]
+    #[cfg(feature = "serde_json")]
+    fn serialize_as_exact_number() {
+        let value = Decimal::parse("001382.570").unwrap();
+
+        assert_eq!(serde_json::to_string(&value).unwrap(), "1382.570");
+        assert_eq!(serde_json::to_string(&(Decimal::ZERO - value)).unwrap(), "-1382.570");
+    }
+
+    #[test]
+    fn parse_rejects_invalid_numbers() {
+        assert_eq!(Decimal::parse(""), None);
+        assert_eq!(Decimal::parse(".5"), None);
+        assert_eq!(Decimal::parse("1.2.3"), None);
+        assert_eq!(Decimal::parse("-1"), None);
+        assert_eq!(Decimal::parse("1e3"), None);
+    }
+
+    #[test]
+    fn exact_arithmetic() {
+        let a = Decimal::parse("0.1").unwrap();
+        let b = Decimal::parse("0.20").unwrap();
+
+        assert_eq!((a + b).to_string(), "0.30");
+        assert_eq!(a + b, Decimal::parse("0.3").unwrap());
+        assert_eq!((a - b).to_string(), "-0.10");
+        assert!(a < b);
+    }
+
+    #[test]
+    fn shift() {
+        assert_eq!(Decimal::parse("1382570").unwrap().checked_shift(3).unwrap().to_string(), "1382.570");
+    }
+
+    #[test]
+    fn checked_arithmetic() {
+        let large = Decimal::parse("9999999999").unwrap();
+        let precise = Decimal::parse("1.000000000").unwrap();
+
+        assert_eq!(large.checked_sub(precise), None);
+        assert_eq!(large.checked_add(precise), None);
+        assert_eq!(precise.checked_sub(precise), Some(Decimal::ZERO));
+        assert_eq!(precise.checked_shift(9), Some(Decimal::new(1, 9)));
+        assert_eq!(precise.checked_shift(10), None);
+    }
+}
@@ -0,0 +1,76 @@
+use std::time::{Duration, Instant};
+use serialport::FlowControl;
+use crate::parser::{FrameParser, Protocol};
+use crate::port::{Port, SerialFormat, SerialSettings};
+use crate::reader::FrameReader;
+
+/// Serial settings tried by `detect_settings` with the protocol of the meters that use them,
+/// most common first.
+pub static CANDIDATES: &[(SerialSettings, Protocol)] = &[
+    (SerialSettings::DSMR4, Protocol::Dsmr4),
+    (SerialSettings::DSMR2, Protocol::Dsmr2),
+    // Converters that change the format, the baud rate tells the protocol
+    (SerialSettings { baud_rate: 115_200, format: SerialFormat::DSMR2, flow_control: FlowControl::None }, Protocol::Dsmr4),
+    (SerialSettings { baud_rate: 9600, format: SerialFormat::DSMR4, flow_control: FlowControl::None }, Protocol::Dsmr2),
+];
+
+/// Find the settings and protocol with which telegrams can be read from a serial device.
+///
+/// Every candidate is opened with `open` and read until a frame is read that the parser of
+/// `parser` for its protocol accepts, or until `timeout` passes. The timeout should be longer
+/// than two telegram intervals, as the first frame is usually incomplete. DSMR 2.2 to 4 meters
+/// send a telegram every 10 seconds.
+pub fn detect_settings(
+    candidates: &[(SerialSettings, Protocol)],
+    mut open: impl FnMut(SerialSettings) -> Box<dyn Port>,
+    parser: impl Fn(Protocol) -> FrameParser,
+    timeout: Duration,
+) -> Option<(SerialSettings, Protocol)> {
+    candidates.iter().copied().find(|(settings, protocol)| {
+        let parser = parser(*protocol);
+        let mut reader = FrameReader::new(open(*settings));
+        let started = Instant::now();
+
+        loop {
+            if reader.read_frames().into_iter().any(|frame| parser.parse(frame).is_ok()) {
+                return true;
+            }
+            if started.elapsed() >= timeout {
+                return false;
+            }
+
+            std::thread::sleep(Duration::from_millis(250));
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use crate::detect::{detect_settings, CANDIDATES};
+    use crate::parser::{FrameParser, Protocol};
+    use crate::port::{PortBuilder, SerialSettings};
+
+    const LEGACY_TELEGRAM: &str = "/ISk5\\2MT382-1003\r\n\r\n0-0:96.1.1(5A424556303035303933313937363132)\r\n1-0:1.8.1(00185.000*kWh)\r\n1-0:1.7.0(0000.98*kW)\r\n!\r\n";
+
+    #[test]
+    fn detect_legacy_meter() {
+        // Reading with the wrong settings gives garbage
+        let open = |settings: SerialSettings| match settings {
+            SerialSettings::DSMR2 => PortBuilder::from_data(LEGACY_TELEGRAM.as_bytes()),
+            _ => PortBuilder::from_data(b"\xf2\x8a/\x93!\xff\r\n"),
+        };
+
+        // The telegram has no checksum, which only the DSMR 2.2 parser accepts
+        let detected = detect_settings(CANDIDATES, open, FrameParser::new, Duration::ZERO);
+
+        assert_eq!(detected, Some((SerialSettings::DSMR2, Protocol::Dsmr2)));
+    }
+
+    #[test]
+    fn nothing_detected() {
+        let open = |_| PortBuilder::from_data(b"");
+
+        assert_eq!(detect_settings(CANDIDATES, open, FrameParser::new, Duration::ZERO), None);
+    }
+}
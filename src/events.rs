@@ -0,0 +1,166 @@
+use std::collections::HashMap;
+use chrono::{DateTime, FixedOffset};
+use crate::data_frame::{DataFrame, Phase, PowerFailureEvent};
+
+/// Keeps track of the power failure events of a meter that were already seen.
+///
+/// The meter repeats its whole power failure log in every telegram. Backends can use this to
+/// only store the events that were added since the previous frame, with a tracker per meter.
+#[derive(Debug, Default)]
+pub struct PowerFailureTracker {
+    last_end: Option<DateTime<FixedOffset>>,
+}
+
+impl PowerFailureTracker {
+    /// Get the events from the log that have not been seen before.
+    pub fn new_events(&mut self, log: &[PowerFailureEvent]) -> Vec<PowerFailureEvent> {
+        let events: Vec<PowerFailureEvent> = log
+            .iter()
+            .filter(|event| self.last_end.is_none_or(|last_end| event.end > last_end))
+            .cloned()
+            .collect();
+
+        if let Some(last) = events.iter().map(|event| event.end).max() {
+            self.last_end = Some(last);
+        }
+
+        events
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum VoltageEventKind {
+    Sag,
+    Swell,
+}
+
+/// One or more voltage sags or swells that occurred on a phase since the previous frame.
+#[derive(Debug, PartialEq, Clone)]
+pub struct VoltageEvent {
+    /// Identity of the meter
+    pub meter: String,
+    /// Time of the frame in which the counter went up
+    pub time: DateTime<FixedOffset>,
+    pub phase: Phase,
+    pub kind: VoltageEventKind,
+    /// Number of events since the previous frame
+    pub count: u32,
+}
+
+/// Sag and swell counters of the phases of a meter.
+#[derive(Debug, Default)]
+struct VoltageCounters {
+    sags: [Option<u32>; 3],
+    swells: [Option<u32>; 3],
+}
+
+/// Detects voltage sags and swells by watching the counters of each meter go up.
+#[derive(Debug, Default)]
+pub struct VoltageEventDetector {
+    meters: HashMap<String, VoltageCounters>,
+}
+
+impl VoltageEventDetector {
+    /// Compare the counters with the previous frame of the same meter. The first frame of a
+    /// meter only sets the baseline.
+    pub fn detect(&mut self, data_frame: &DataFrame) -> Vec<VoltageEvent> {
+        let meter = data_frame.meter_identity();
+        let counters = self.meters.entry(meter.clone()).or_default();
+        let mut events = Vec::new();
+
+        for (index, phase) in Phase::ALL.iter().enumerate() {
+            let data = data_frame.data.phase(*phase);
+
+            let counters = [
+                (VoltageEventKind::Sag, &mut counters.sags[index], data.voltage_sags),
+                (VoltageEventKind::Swell, &mut counters.swells[index], data.voltage_swells),
+            ];
+
+            for (kind, previous, current) in counters {
+                if let (Some(previous), Some(current)) = (*previous, current) {
+                    // A lower value means the meter was reset, which is not an event
+                    if current > previous {
+                        events.push(VoltageEvent {
+                            meter: meter.clone(),
+                            time: data_frame.time,
+                            phase: *phase,
+                            kind,
+                            count: current - previous,
+                        });
+                    }
+                }
+
+                if current.is_some() {
+                    *previous = current;
+                }
+            }
+        }
+
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use chrono::{FixedOffset, TimeZone};
+    use crate::data_frame::{DataFrame, Object, Phase, PowerFailureEvent, TelegramObject};
+    use crate::obis::ObisCode;
+    use crate::events::{PowerFailureTracker, VoltageEvent, VoltageEventDetector, VoltageEventKind};
+
+    fn event(day: u32, duration: u64) -> PowerFailureEvent {
+        PowerFailureEvent {
+            end: FixedOffset::east_opt(3600).unwrap().with_ymd_and_hms(2021, 12, day, 12, 0, 0).unwrap(),
+            duration: Duration::from_secs(duration),
+        }
+    }
+
+    #[test]
+    fn only_new_power_failures() {
+        let mut tracker = PowerFailureTracker::default();
+
+        assert_eq!(tracker.new_events(&[event(1, 10), event(2, 20)]).len(), 2);
+        assert_eq!(tracker.new_events(&[event(1, 10), event(2, 20)]).len(), 0);
+        assert_eq!(tracker.new_events(&[event(2, 20), event(3, 30)]), vec![event(3, 30)]);
+    }
+
+    fn frame(sags: u32, swells: u32) -> DataFrame {
+        let objects = vec![
+            TelegramObject::new(ObisCode::new(1, 0, 32, 32, 0), Object::VoltageSags(Phase::L1, sags)),
+            TelegramObject::new(ObisCode::new(1, 0, 32, 36, 0), Object::VoltageSwells(Phase::L1, swells)),
+        ];
+
+        DataFrame::new(String::from("ISK"), String::new(), objects, None, 0)
+    }
+
+    #[test]
+    fn voltage_events() {
+        let mut detector = VoltageEventDetector::default();
+
+        let first = frame(6, 1);
+        assert_eq!(detector.detect(&first), vec![]);
+
+        let second = frame(8, 1);
+        assert_eq!(detector.detect(&second), vec![VoltageEvent {
+            meter: String::from("ISK"),
+            time: second.time,
+            phase: Phase::L1,
+            kind: VoltageEventKind::Sag,
+            count: 2,
+        }]);
+
+        let reset = frame(0, 0);
+        assert_eq!(detector.detect(&reset), vec![]);
+    }
+
+    #[test]
+    fn voltage_events_per_meter() {
+        let mut detector = VoltageEventDetector::default();
+        detector.detect(&frame(6, 1));
+
+        // The first frame of another meter only sets its baseline
+        let other = DataFrame::new(String::from("KFM"), String::new(), frame(8, 1).objects().to_vec(), None, 0);
+        assert_eq!(detector.detect(&other), vec![]);
+        assert_eq!(detector.detect(&frame(6, 1)), vec![]);
+    }
+}
@@ -0,0 +1,22 @@
+//! Reading, parsing and storing the P1 telegrams of DSMR and e-MUCS smart meters.
+//!
+//! The collector binary is built on this library. Other programs can use it to parse
+//! telegrams, with their own [parser::ObjectHandler]s for objects the parser does not know.
+
+pub mod backend;
+mod crc;
+pub mod data_frame;
+pub mod decimal;
+pub mod detect;
+pub mod events;
+pub mod meter;
+pub mod obis;
+pub mod parser;
+pub mod port;
+pub mod reader;
+pub mod serializer;
+#[cfg(feature = "smarty")]
+pub mod smarty;
+#[cfg(feature = "async")]
+pub mod stream;
+pub mod validation;
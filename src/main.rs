@@ -1,21 +1,20 @@
+use std::collections::HashSet;
 use std::time::Duration;
-use crate::port::PortBuilder;
-use crate::reader::FrameReader;
-use crate::data_frame::DataFrame;
-use crate::parser::FrameParser;
+use serialport::FlowControl;
+use dsmr_collector::{detect, parser};
+use dsmr_collector::port::{self, BufferOptions, OverflowPolicy, PortBuilder, SerialFormat, SerialSettings};
+use dsmr_collector::reader::FrameReader;
+use dsmr_collector::data_frame::Object;
+use dsmr_collector::parser::{FrameParser, ParseMode, Protocol};
+use dsmr_collector::events::VoltageEventDetector;
+use dsmr_collector::validation::{PlausibilityAction, PlausibilityChecker};
 use clap::Parser;
 
-use crate::backend::Backend;
+use dsmr_collector::backend::Backend;
 #[cfg(feature = "database")]
-use crate::backend::Database;
+use dsmr_collector::backend::Database;
 #[cfg(feature = "api")]
-use crate::backend::DSMRAPI;
-
-mod port;
-mod parser;
-mod data_frame;
-mod reader;
-mod backend;
+use dsmr_collector::backend::DSMRAPI;
 
 /// Simple program to greet a person
 #[derive(Parser, Debug)]
@@ -40,6 +39,68 @@ struct Args {
     #[clap(long)]
     api_key: Option<String>,
 
+    /// Decryption key of a Luxembourg Smarty meter, as 32 hex characters
+    #[cfg(feature = "smarty")]
+    #[clap(long)]
+    smarty_key: Option<String>,
+
+    /// Additional authenticated data for Smarty decryption, as 32 hex characters
+    #[cfg(feature = "smarty")]
+    #[clap(long)]
+    smarty_aad: Option<String>,
+
+    /// Time zone of the meter, used to resolve its timestamps
+    #[clap(long, default_value = "Europe/Amsterdam")]
+    timezone: chrono_tz::Tz,
+
+    /// Parse telegrams of DSMR 2.2 and 3.0 meters, which have no checksum
+    #[clap(long)]
+    legacy: bool,
+
+    /// Skip objects that can not be parsed instead of the whole telegram
+    #[clap(long)]
+    lenient: bool,
+
+    /// What to do with frames that are not plausible compared to the previous frames:
+    /// drop, quarantine or tag
+    #[clap(long, default_value = "tag")]
+    implausible: PlausibilityAction,
+
+    /// Maximum power of the connection in kW, to detect impossible jumps of the counters
+    #[clap(long, default_value = "50")]
+    max_power: f64,
+
+    /// Baud rate of the serial device
+    #[clap(long, default_value = "115200")]
+    baud_rate: u32,
+
+    /// Data bits, parity and stop bits of the serial device, e.g. 8N1, or 7E1 for DSMR 2.2 and
+    /// 3.0 meters
+    #[clap(long, default_value = "8N1")]
+    serial_format: SerialFormat,
+
+    /// Flow control of the serial device: none, software or hardware
+    #[clap(long, default_value = "none", parse(try_from_str = port::parse_flow_control))]
+    flow_control: FlowControl,
+
+    /// Try the common serial settings until valid telegrams are read, instead of using the
+    /// given ones. This also detects whether the meter uses the legacy protocol
+    #[clap(long)]
+    detect_serial: bool,
+
+    /// Size in bytes of the buffer for data from the serial port
+    #[clap(long, default_value = "4096")]
+    buffer_size: usize,
+
+    /// What to do with data from the serial port when the buffer is full:
+    /// drop-oldest, drop-newest or block
+    #[clap(long, default_value = "drop-newest")]
+    overflow: OverflowPolicy,
+
+    /// Size in bytes above which frames are discarded
+    #[clap(long, default_value = "8192")]
+    max_frame_size: usize,
+
     /// Verbose output
     #[clap(short, long)]
     verbose: bool,
@@ -53,31 +114,167 @@ fn main() {
         return;
     }
 
-    let port = PortBuilder::from_path(&args.input);
-    let mut frame_reader = FrameReader::new(port);
+    #[cfg(feature = "smarty")]
+    let smarty_keys = match &args.smarty_key {
+        Some(key) => {
+            let key = match parse_key(key) {
+                Some(key) => key,
+                None => {
+                    println!("Option 'smarty-key' must be 32 hex characters.");
+                    return;
+                }
+            };
+            let aad = match args.smarty_aad.as_deref().map(parse_key) {
+                Some(None) => {
+                    println!("Option 'smarty-aad' must be 32 hex characters.");
+                    return;
+                }
+                Some(aad) => aad,
+                None => None,
+            };
+
+            Some((key, aad))
+        }
+        None => None,
+    };
+
+    let buffer = BufferOptions {
+        size: args.buffer_size,
+        overflow: args.overflow,
+    };
+    let open_port = |serial: SerialSettings| {
+        let port = PortBuilder::from_path_with_settings(&args.input, serial, buffer);
+
+        #[cfg(feature = "smarty")]
+        let port = match smarty_keys {
+            Some((key, aad)) => PortBuilder::decrypting(port, key, aad),
+            None => port,
+        };
+
+        port
+    };
+
+    let make_parser = |protocol: Protocol| FrameParser::new(protocol)
+        .with_time_zone(args.timezone)
+        .with_mode(if args.lenient { ParseMode::Lenient } else { ParseMode::Strict });
+
+    let (serial, protocol) = if args.detect_serial {
+        println!("Detecting the serial settings of {}", args.input);
+
+        match detect::detect_settings(detect::CANDIDATES, open_port, make_parser, Duration::from_secs(25)) {
+            Some((serial, protocol)) => {
+                println!("Detected serial settings: {} ({:?})", serial, protocol);
+                (serial, protocol)
+            }
+            None => {
+                println!("No valid telegrams were read with any of the serial settings.");
+                return;
+            }
+        }
+    } else {
+        let serial = SerialSettings {
+            baud_rate: args.baud_rate,
+            format: args.serial_format,
+            flow_control: args.flow_control,
+        };
+
+        (serial, if args.legacy { Protocol::Dsmr2 } else { Protocol::Dsmr4 })
+    };
+    let parser = make_parser(protocol);
+    let mut frame_reader = FrameReader::new(open_port(serial)).with_max_frame_size(args.max_frame_size);
 
     // let mut backend = Database::new("postgres://pi:pi@localhost".to_string());
     let mut backend = make_backend(&args);
     backend.init().unwrap();
 
+    let mut voltage_events = VoltageEventDetector::default();
+    let mut plausibility = PlausibilityChecker::new(args.max_power);
+    let mut meters = HashSet::new();
+    let mut statistics = frame_reader.statistics();
+
     loop {
-        if let Some(raw_frame) = frame_reader.read_next_byte() {
-            let data_frame = FrameParser::parse(raw_frame).unwrap();
+        let raw_frames = frame_reader.read_frames();
+
+        let previous = std::mem::replace(&mut statistics, frame_reader.statistics());
+        if statistics.lost_bytes > previous.lost_bytes {
+            println!("WARNING: Lost {} byte(s) because the buffer overflowed ({} times in total)",
+                     statistics.lost_bytes - previous.lost_bytes,
+                     statistics.overflows,
+            );
+        }
+        if statistics.discarded_bytes > previous.discarded_bytes {
+            println!("WARNING: Discarded {} frame(s) and {} byte(s), check the connection with the meter ({} frames read, {} frames and {} bytes discarded in total)",
+                     statistics.discarded_frames - previous.discarded_frames,
+                     statistics.discarded_bytes - previous.discarded_bytes,
+                     statistics.frames,
+                     statistics.discarded_frames,
+                     statistics.discarded_bytes,
+            );
+        }
+        if raw_frames.is_empty() {
+            // DSMR only does frames every 1 second.
+            std::thread::sleep(Duration::from_millis(250));
+        }
+
+        for raw_frame in raw_frames {
+            let data_frame = match parser.parse(raw_frame) {
+                Ok(data_frame) => data_frame,
+                Err(e) => {
+                    println!("ERROR: Skipping frame that could not be parsed: {}", e);
+                    continue;
+                }
+            };
+
+            if meters.insert(data_frame.meter_identity()) {
+                println!("Reading meter {}: {}", data_frame.meter_identity(), data_frame.meter_description());
+            }
+
+            for e in data_frame.skipped() {
+                println!("WARNING: Skipped object that could not be parsed: {}", e);
+            }
+
+            let implausibilities = plausibility.check(&data_frame);
+            for i in &implausibilities {
+                println!("WARNING: Frame of {:?} is not plausible: {}", data_frame.time, i);
+            }
+
+            let data_frame = match args.implausible {
+                _ if implausibilities.is_empty() => data_frame,
+                PlausibilityAction::Drop => continue,
+                PlausibilityAction::Quarantine => {
+                    backend.quarantine(&data_frame, &implausibilities).unwrap();
+                    continue;
+                }
+                PlausibilityAction::Tag => data_frame.with_implausibilities(implausibilities),
+            };
 
             if args.verbose {
-                println!("[{:?}]: {:?} kW ({:?} + {:?} kWh on meter), {:?} m3 gas on meter",
+                println!("[{:?}]: {:?} kW ({} + {} kWh on meter), {} m3 gas on meter",
                          data_frame.time,
                          data_frame.data.electricity_delivering,
                          data_frame.data.electricity_delivered_t1,
                          data_frame.data.electricity_delivered_t2,
                          data_frame.data.gas_delivered,
                 );
+
+                for object in data_frame.objects() {
+                    match &object.object {
+                        Object::Custom(obis, value) => println!("[{:?}]: {}: {:?}", data_frame.time, obis, value),
+                        Object::Unknown(obis, value) => match obis.info() {
+                            Some(info) => println!("[{:?}]: {} ({}): {}", data_frame.time, info.name, obis, value),
+                            None => println!("[{:?}]: Unknown object {}{}", data_frame.time, obis, value),
+                        },
+                        _ => {}
+                    }
+                }
             }
 
             backend.send(&data_frame).unwrap();
 
-            // DSMR only does frames every 1 second.
-            std::thread::sleep(Duration::from_millis(250));
+            for event in voltage_events.detect(&data_frame) {
+                println!("[{:?}]: {} voltage {:?}(s) on {:?}", event.time, event.count, event.kind, event.phase);
+                backend.send_voltage_event(&event).unwrap();
+            }
         }
     }
 }
@@ -97,3 +294,9 @@ fn make_backend(args: &Args) -> Box<dyn Backend> {
 
     panic!("Either 'api' or 'database' is required'");
 }
+
+/// Parse a 128 bit key from hex
+#[cfg(feature = "smarty")]
+fn parse_key(key: &str) -> Option<[u8; 16]> {
+    parser::decode_hex(key)?.try_into().ok()
+}
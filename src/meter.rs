@@ -0,0 +1,147 @@
+use std::time::Duration;
+
+/// Manufacturer with its flag, the three letters at the start of the header.
+#[derive(Debug)]
+pub struct Manufacturer {
+    pub flag: &'static str,
+    pub name: &'static str,
+}
+
+/// A meter model, recognized by a string in the identification of the header.
+#[derive(Debug)]
+pub struct MeterModel {
+    pub flag: &'static str,
+    /// Part of the identification that is unique to the model
+    pub pattern: &'static str,
+    pub name: &'static str,
+    /// Number of phases, if all meters of the model have the same
+    pub phases: Option<u8>,
+    /// DSMR version as sent in the version object, e.g. 42 for 4.2
+    pub version: Option<u32>,
+}
+
+/// Manufacturer flags as registered with the DLMS User Association, for the meters used with DSMR
+/// and e-MUCS.
+pub static MANUFACTURERS: &[Manufacturer] = &[
+    Manufacturer { flag: "ISK", name: "Iskraemeco" },
+    Manufacturer { flag: "KFM", name: "Kaifa" },
+    Manufacturer { flag: "XMX", name: "Landis+Gyr" },
+    Manufacturer { flag: "KMP", name: "Kamstrup" },
+    Manufacturer { flag: "Ene", name: "Sagemcom" },
+    Manufacturer { flag: "SAG", name: "Sagemcom" },
+    Manufacturer { flag: "FLU", name: "Fluvius" },
+];
+
+pub static MODELS: &[MeterModel] = &[
+    MeterModel { flag: "ISK", pattern: "2M550E", name: "AM550", phases: Some(1), version: Some(50) },
+    MeterModel { flag: "ISK", pattern: "2M550T", name: "AM550", phases: Some(3), version: Some(50) },
+    MeterModel { flag: "ISK", pattern: "2ME382", name: "ME382", phases: Some(1), version: None },
+    MeterModel { flag: "ISK", pattern: "2MT382", name: "MT382", phases: Some(3), version: None },
+    MeterModel { flag: "KFM", pattern: "KAIFA-METER", name: "MA105/MA304", phases: None, version: Some(42) },
+    MeterModel { flag: "XMX", pattern: "LGBB", name: "E350", phases: None, version: Some(42) },
+    MeterModel { flag: "XMX", pattern: "LGF", name: "E360", phases: None, version: Some(50) },
+    MeterModel { flag: "KMP", pattern: "KA6U", name: "162/382", phases: None, version: Some(40) },
+    MeterModel { flag: "Ene", pattern: "XS210", name: "XS210", phases: Some(1), version: Some(50) },
+    MeterModel { flag: "Ene", pattern: "T210-D", name: "T210-D", phases: Some(3), version: Some(50) },
+];
+
+/// What is known about a meter from the header and the contents of its telegrams.
+#[derive(Debug, PartialEq, Clone)]
+pub struct MeterDescription {
+    /// Name of the manufacturer, or the flag if it is not known
+    pub manufacturer: String,
+    pub model: Option<&'static str>,
+    pub phases: Option<u8>,
+    /// DSMR version as sent in the version object, e.g. 42 for 4.2
+    pub version: Option<u32>,
+    /// Time between telegrams
+    pub interval: Option<Duration>,
+}
+
+impl MeterDescription {
+    /// Describe a meter by its header. Capabilities that the model does not tell are taken
+    /// from the telegram: the DSMR `version` (0 if not sent), the number of `phases` with
+    /// values, and whether it is an e-MUCS meter.
+    pub fn identify(flag: &str, identifier: &str, version: u32, phases: Option<u8>, emucs: bool) -> Self {
+        // The case of the last letter tells the reaction time of the meter
+        let matches = |other: &str| other.eq_ignore_ascii_case(flag);
+
+        let manufacturer = MANUFACTURERS
+            .iter()
+            .find(|m| matches(m.flag))
+            .map_or_else(|| flag.to_string(), |m| m.name.to_string());
+        let model = MODELS
+            .iter()
+            .find(|m| matches(m.flag) && identifier.contains(m.pattern));
+
+        let version = model.and_then(|m| m.version).or(Some(version).filter(|v| *v != 0));
+        let interval = match version {
+            _ if emucs => Some(Duration::from_secs(1)),
+            Some(version) if version >= 50 => Some(Duration::from_secs(1)),
+            Some(_) => Some(Duration::from_secs(10)),
+            None => None,
+        };
+
+        Self {
+            manufacturer,
+            model: model.map(|m| m.name),
+            phases: model.and_then(|m| m.phases).or(phases),
+            version,
+            interval,
+        }
+    }
+}
+
+impl std::fmt::Display for MeterDescription {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.manufacturer)?;
+
+        if let Some(model) = self.model {
+            write!(f, " {}", model)?;
+        }
+        if let Some(phases) = self.phases {
+            write!(f, ", {} phase{}", phases, if phases == 1 { "" } else { "s" })?;
+        }
+        if let Some(version) = self.version {
+            write!(f, ", DSMR {}.{}", version / 10, version % 10)?;
+        }
+        if let Some(interval) = self.interval {
+            write!(f, ", every {}s", interval.as_secs())?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use crate::meter::MeterDescription;
+
+    #[test]
+    fn known_model() {
+        let meter = MeterDescription::identify("ISK", "\\2M550E-1012", 50, Some(3), false);
+
+        assert_eq!(meter.manufacturer, "Iskraemeco");
+        assert_eq!(meter.model, Some("AM550"));
+        assert_eq!(meter.phases, Some(1));
+        assert_eq!(meter.interval, Some(Duration::from_secs(1)));
+        assert_eq!(meter.to_string(), "Iskraemeco AM550, 1 phase, DSMR 5.0, every 1s");
+    }
+
+    #[test]
+    fn flag_is_case_insensitive() {
+        let meter = MeterDescription::identify("ISk", "\\2ME382-1003", 0, None, false);
+
+        assert_eq!(meter.to_string(), "Iskraemeco ME382, 1 phase");
+    }
+
+    #[test]
+    fn unknown_model_uses_telegram() {
+        let meter = MeterDescription::identify("KFM", "\\2UNKNOWN", 42, Some(3), false);
+
+        assert_eq!(meter.to_string(), "Kaifa, 3 phases, DSMR 4.2, every 10s");
+        assert_eq!(MeterDescription::identify("FLU", "\\253769484_A", 0, Some(1), true).to_string(), "Fluvius, 1 phase, every 1s");
+        assert_eq!(MeterDescription::identify("ABC", "", 0, None, false).to_string(), "ABC");
+    }
+}
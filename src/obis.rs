@@ -0,0 +1,229 @@
+use std::str::FromStr;
+use crate::data_frame::Unit;
+
+/// OBIS reference of an object, with format A-B:C.D.E*F.
+///
+/// Telegrams leave out the F group, which is then 255. For M-Bus objects, B is the channel of
+/// the device.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub struct ObisCode {
+    pub a: u8,
+    pub b: u8,
+    pub c: u8,
+    pub d: u8,
+    pub e: u8,
+    pub f: u8,
+}
+
+impl ObisCode {
+    pub const fn new(a: u8, b: u8, c: u8, d: u8, e: u8) -> Self {
+        Self { a, b, c, d, e, f: 255 }
+    }
+
+    /// Parse a code like `1-0:1.8.1`. The F group can be given as `*255` or `.255`.
+    pub fn parse(input: &str) -> Option<Self> {
+        let (a, rest) = input.split_once('-')?;
+        let (b, rest) = rest.split_once(':')?;
+        let mut groups = rest.split(['.', '*']);
+
+        let group = |value: Option<&str>| -> Option<u8> {
+            let value = value?;
+            if value.is_empty() || !value.bytes().all(|c| c.is_ascii_digit()) {
+                return None;
+            }
+            value.parse().ok()
+        };
+
+        let code = Self {
+            a: group(Some(a))?,
+            b: group(Some(b))?,
+            c: group(groups.next())?,
+            d: group(groups.next())?,
+            e: group(groups.next())?,
+            f: match groups.next() {
+                Some(f) => group(Some(f))?,
+                None => 255,
+            },
+        };
+
+        match groups.next() {
+            Some(_) => None,
+            None => Some(code),
+        }
+    }
+
+    /// M-Bus channel of the device this object belongs to, 1 to 4.
+    pub fn mbus_channel(&self) -> Option<u8> {
+        Some(self.b).filter(|channel| self.a == 0 && (1..=4).contains(channel))
+    }
+
+    /// Description of this code, if it is defined by DSMR or e-MUCS.
+    pub fn info(&self) -> Option<&'static ObisInfo> {
+        REGISTRY.iter().find(|info| {
+            info.code == *self || (info.mbus && self.mbus_channel().is_some() && info.code == Self { b: 1, ..*self })
+        })
+    }
+}
+
+impl FromStr for ObisCode {
+    type Err = ();
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        Self::parse(input).ok_or(())
+    }
+}
+
+impl std::fmt::Display for ObisCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}-{}:{}.{}.{}", self.a, self.b, self.c, self.d, self.e)?;
+
+        if self.f != 255 {
+            write!(f, "*{}", self.f)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Format of the value of an object.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ValueFormat {
+    /// Number without decimals or unit
+    Integer,
+    /// Number with a unit
+    Decimal,
+    /// Timestamp with DST flag
+    Timestamp,
+    /// Hex encoded text
+    OctetString,
+    /// Timestamp followed by a number with a unit
+    TimestampedDecimal,
+    /// Count followed by a list of entries
+    Buffer,
+}
+
+/// Description of a known OBIS code.
+#[derive(Debug)]
+pub struct ObisInfo {
+    /// Code of the object. For M-Bus objects, this is the code on channel 1.
+    pub code: ObisCode,
+    pub name: &'static str,
+    pub description: &'static str,
+    /// Units the value can be sent in. Empty for values without a unit.
+    pub units: &'static [Unit],
+    pub format: ValueFormat,
+    /// Whether the object exists for every M-Bus channel.
+    pub mbus: bool,
+}
+
+pub const ENERGY: &[Unit] = &[Unit::KWh, Unit::Wh];
+pub const POWER: &[Unit] = &[Unit::KW, Unit::W];
+pub const VOLTAGE: &[Unit] = &[Unit::V];
+pub const CURRENT: &[Unit] = &[Unit::A];
+pub const DURATION: &[Unit] = &[Unit::S];
+/// Counters of gas, water, heat and cooling meters
+pub const MBUS: &[Unit] = &[Unit::M3, Unit::GJ, Unit::KWh, Unit::Wh];
+
+const fn info(code: ObisCode, name: &'static str, description: &'static str, units: &'static [Unit], format: ValueFormat) -> ObisInfo {
+    ObisInfo { code, name, description, units, format, mbus: false }
+}
+
+const fn mbus(code: ObisCode, name: &'static str, description: &'static str, units: &'static [Unit], format: ValueFormat) -> ObisInfo {
+    ObisInfo { code, name, description, units, format, mbus: true }
+}
+
+/// Objects defined by DSMR 2.2 up to 5.0.2 and e-MUCS.
+pub static REGISTRY: &[ObisInfo] = &[
+    info(ObisCode::new(1, 3, 0, 2, 8), "Version", "Version of the P1 output", &[], ValueFormat::Integer),
+    info(ObisCode::new(0, 0, 1, 0, 0), "Timestamp", "Date and time of the telegram", &[], ValueFormat::Timestamp),
+    info(ObisCode::new(0, 0, 96, 1, 1), "Equipment identifier", "Serial number of the electricity meter", &[], ValueFormat::OctetString),
+    info(ObisCode::new(0, 0, 96, 13, 0), "Text message", "Text message from the grid operator", &[], ValueFormat::OctetString),
+    info(ObisCode::new(0, 0, 96, 13, 1), "Message code", "Numeric message from the grid operator (DSMR 2.2 and 3.0)", &[], ValueFormat::OctetString),
+
+    info(ObisCode::new(1, 0, 1, 8, 1), "Delivered T1", "Electricity delivered to the client in tariff 1", ENERGY, ValueFormat::Decimal),
+    info(ObisCode::new(1, 0, 1, 8, 2), "Delivered T2", "Electricity delivered to the client in tariff 2", ENERGY, ValueFormat::Decimal),
+    info(ObisCode::new(1, 0, 2, 8, 1), "Received T1", "Electricity delivered by the client in tariff 1", ENERGY, ValueFormat::Decimal),
+    info(ObisCode::new(1, 0, 2, 8, 2), "Received T2", "Electricity delivered by the client in tariff 2", ENERGY, ValueFormat::Decimal),
+    info(ObisCode::new(0, 0, 96, 14, 0), "Tariff indicator", "Active tariff", &[], ValueFormat::Integer),
+    info(ObisCode::new(1, 0, 1, 7, 0), "Delivering", "Power delivered to the client", POWER, ValueFormat::Decimal),
+    info(ObisCode::new(1, 0, 2, 7, 0), "Receiving", "Power delivered by the client", POWER, ValueFormat::Decimal),
+    info(ObisCode::new(0, 0, 17, 0, 0), "Threshold", "Threshold of the electricity supply", &[Unit::KW, Unit::A], ValueFormat::Decimal),
+    info(ObisCode::new(0, 0, 96, 3, 10), "Switch position", "Position of the electricity switch", &[], ValueFormat::Integer),
+
+    info(ObisCode::new(0, 0, 96, 7, 21), "Power failures", "Number of power failures in any phase", &[], ValueFormat::Integer),
+    info(ObisCode::new(0, 0, 96, 7, 9), "Long power failures", "Number of long power failures in any phase", &[], ValueFormat::Integer),
+    info(ObisCode::new(1, 0, 99, 97, 0), "Power failure log", "End time and duration of long power failures", DURATION, ValueFormat::Buffer),
+
+    info(ObisCode::new(1, 0, 32, 32, 0), "Voltage sags L1", "Number of voltage sags in phase L1", &[], ValueFormat::Integer),
+    info(ObisCode::new(1, 0, 52, 32, 0), "Voltage sags L2", "Number of voltage sags in phase L2", &[], ValueFormat::Integer),
+    info(ObisCode::new(1, 0, 72, 32, 0), "Voltage sags L3", "Number of voltage sags in phase L3", &[], ValueFormat::Integer),
+    info(ObisCode::new(1, 0, 32, 36, 0), "Voltage swells L1", "Number of voltage swells in phase L1", &[], ValueFormat::Integer),
+    info(ObisCode::new(1, 0, 52, 36, 0), "Voltage swells L2", "Number of voltage swells in phase L2", &[], ValueFormat::Integer),
+    info(ObisCode::new(1, 0, 72, 36, 0), "Voltage swells L3", "Number of voltage swells in phase L3", &[], ValueFormat::Integer),
+    info(ObisCode::new(1, 0, 32, 7, 0), "Voltage L1", "Instantaneous voltage of phase L1", VOLTAGE, ValueFormat::Decimal),
+    info(ObisCode::new(1, 0, 52, 7, 0), "Voltage L2", "Instantaneous voltage of phase L2", VOLTAGE, ValueFormat::Decimal),
+    info(ObisCode::new(1, 0, 72, 7, 0), "Voltage L3", "Instantaneous voltage of phase L3", VOLTAGE, ValueFormat::Decimal),
+    info(ObisCode::new(1, 0, 31, 7, 0), "Current L1", "Instantaneous current of phase L1", CURRENT, ValueFormat::Decimal),
+    info(ObisCode::new(1, 0, 51, 7, 0), "Current L2", "Instantaneous current of phase L2", CURRENT, ValueFormat::Decimal),
+    info(ObisCode::new(1, 0, 71, 7, 0), "Current L3", "Instantaneous current of phase L3", CURRENT, ValueFormat::Decimal),
+    info(ObisCode::new(1, 0, 21, 7, 0), "Delivering L1", "Power delivered to the client in phase L1", POWER, ValueFormat::Decimal),
+    info(ObisCode::new(1, 0, 41, 7, 0), "Delivering L2", "Power delivered to the client in phase L2", POWER, ValueFormat::Decimal),
+    info(ObisCode::new(1, 0, 61, 7, 0), "Delivering L3", "Power delivered to the client in phase L3", POWER, ValueFormat::Decimal),
+    info(ObisCode::new(1, 0, 22, 7, 0), "Receiving L1", "Power delivered by the client in phase L1", POWER, ValueFormat::Decimal),
+    info(ObisCode::new(1, 0, 42, 7, 0), "Receiving L2", "Power delivered by the client in phase L2", POWER, ValueFormat::Decimal),
+    info(ObisCode::new(1, 0, 62, 7, 0), "Receiving L3", "Power delivered by the client in phase L3", POWER, ValueFormat::Decimal),
+
+    info(ObisCode::new(0, 0, 96, 1, 4), "e-MUCS version", "Version of the e-MUCS specification", &[], ValueFormat::Integer),
+    info(ObisCode::new(1, 0, 1, 4, 0), "Average demand", "Current 15 minute average demand", POWER, ValueFormat::Decimal),
+    info(ObisCode::new(1, 0, 1, 6, 0), "Maximum demand", "Highest 15 minute average demand of the running month", POWER, ValueFormat::TimestampedDecimal),
+    info(ObisCode::new(0, 0, 98, 1, 0), "Maximum demand history", "Highest 15 minute average demand of the last 13 months", POWER, ValueFormat::Buffer),
+
+    mbus(ObisCode::new(0, 1, 24, 1, 0), "Device type", "Type of the M-Bus device", &[], ValueFormat::Integer),
+    mbus(ObisCode::new(0, 1, 96, 1, 0), "Device identifier", "Serial number of the M-Bus device", &[], ValueFormat::OctetString),
+    mbus(ObisCode::new(0, 1, 96, 1, 1), "Device identifier", "Serial number of the M-Bus device (e-MUCS)", &[], ValueFormat::OctetString),
+    mbus(ObisCode::new(0, 1, 24, 2, 1), "Device reading", "Last reading of the M-Bus device", MBUS, ValueFormat::TimestampedDecimal),
+    mbus(ObisCode::new(0, 1, 24, 2, 3), "Device reading", "Last reading of the M-Bus device, without temperature correction (e-MUCS)", MBUS, ValueFormat::TimestampedDecimal),
+    mbus(ObisCode::new(0, 1, 24, 3, 0), "Device reading", "Last reading of the M-Bus device (DSMR 2.2 and 3.0)", MBUS, ValueFormat::Buffer),
+    mbus(ObisCode::new(0, 1, 24, 4, 0), "Valve position", "Position of the gas valve (DSMR 2.2 and 3.0)", &[], ValueFormat::Integer),
+];
+
+#[cfg(test)]
+mod tests {
+    use crate::obis::{ObisCode, ValueFormat};
+
+    #[test]
+    fn parse_and_format() {
+        let code = ObisCode::parse("1-0:1.8.1").unwrap();
+
+        assert_eq!(code, ObisCode::new(1, 0, 1, 8, 1));
+        assert_eq!(code.to_string(), "1-0:1.8.1");
+        assert_eq!(ObisCode::parse("1-0:1.8.1*255"), Some(code));
+        assert_eq!(ObisCode::parse("1-0:1.8.1.255"), Some(code));
+        assert_eq!(ObisCode::parse("1-0:99.97.0*101").unwrap().to_string(), "1-0:99.97.0*101");
+    }
+
+    #[test]
+    fn parse_invalid() {
+        assert_eq!(ObisCode::parse(""), None);
+        assert_eq!(ObisCode::parse("1-0:1.8"), None);
+        assert_eq!(ObisCode::parse("1-0:1.8.1.255.0"), None);
+        assert_eq!(ObisCode::parse("1-0:1.8.256"), None);
+        assert_eq!(ObisCode::parse("1-0:1.8.+1"), None);
+    }
+
+    #[test]
+    fn ordering() {
+        let mut codes = vec![ObisCode::new(1, 0, 2, 8, 1), ObisCode::new(0, 0, 96, 1, 1), ObisCode::new(1, 0, 1, 8, 2)];
+        codes.sort();
+
+        assert_eq!(codes, vec![ObisCode::new(0, 0, 96, 1, 1), ObisCode::new(1, 0, 1, 8, 2), ObisCode::new(1, 0, 2, 8, 1)]);
+    }
+
+    #[test]
+    fn registry() {
+        assert_eq!(ObisCode::new(1, 0, 1, 8, 1).info().unwrap().name, "Delivered T1");
+        assert_eq!(ObisCode::new(0, 3, 24, 2, 1).info().unwrap().format, ValueFormat::TimestampedDecimal);
+        assert!(ObisCode::new(0, 5, 24, 2, 1).info().is_none());
+        assert!(ObisCode::new(1, 0, 1, 8, 3).info().is_none());
+    }
+}
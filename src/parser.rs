@@ -1,47 +1,287 @@
-use chrono::{DateTime, Local, TimeZone};
-use crate::data_frame::{DataFrame, Object, RawFrame};
-use nom::{IResult, bytes::complete::{take_while_m_n, take_till}, character::complete::{char}, sequence::tuple, AsChar};
-use nom::bytes::complete::is_a;
-use nom::character::complete::{alphanumeric0, crlf, one_of};
-use nom::combinator::{map_res, not, peek, recognize};
-use nom::multi::many1;
-
-#[derive(Debug)]
+use chrono::{DateTime, FixedOffset, LocalResult, NaiveDateTime, Offset, TimeZone};
+use chrono_tz::{OffsetComponents, Tz};
+use crate::crc::crc16;
+use crate::decimal::Decimal;
+use crate::obis::{ObisCode, DURATION, POWER};
+use std::time::Duration;
+use crate::data_frame::{DataFrame, DemandPeak, MBusDeviceType, MonthlyDemandPeak, MBusReading, Measurement, Object, Phase, PowerFailureEvent, RawFrame, TelegramObject, Unit};
+use nom::{IResult, bytes::complete::{take_while_m_n, take_till, take_till1}, character::complete::{char}, sequence::tuple, AsChar};
+use nom::branch::alt;
+use nom::character::complete::{alphanumeric1, crlf, one_of, satisfy};
+use nom::bytes::complete::tag;
+use nom::combinator::{map, map_opt, map_res, opt, recognize, verify};
+use nom::error::{ErrorKind, FromExternalError};
+use nom::multi::{count, many0};
+use nom::sequence::delimited;
+
+/// Error of a telegram that could not be parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ParseError {
-    Invalid,
+    /// The telegram does not start with a valid "/XXX5identification" header.
+    InvalidHeader,
+    /// A line is not an object with format "obis(value)".
+    InvalidLine { line: usize },
+    /// The value of an object could not be parsed.
+    InvalidObject { line: usize, obis: ObisCode, kind: ObjectErrorKind },
+    /// The telegram ends before the footer.
+    MissingFooter,
+    /// The footer is not a "!" followed by the checksum.
+    InvalidFooter,
+    /// The checksum in the footer does not match the contents of the telegram.
+    ChecksumMismatch { expected: u16, calculated: u16 },
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseError::InvalidHeader => write!(f, "invalid header"),
+            ParseError::InvalidLine { line } => write!(f, "line {} is not an object", line),
+            ParseError::InvalidObject { line, obis, kind } => match obis.info() {
+                Some(info) => write!(f, "line {} ({}, {}): {}", line, obis, info.name, kind),
+                None => write!(f, "line {} ({}): {}", line, obis, kind),
+            },
+            ParseError::MissingFooter => write!(f, "missing footer"),
+            ParseError::InvalidFooter => write!(f, "invalid footer"),
+            ParseError::ChecksumMismatch { expected, calculated } =>
+                write!(f, "checksum mismatch (expected {:04X}, calculated {:04X})", expected, calculated),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Reason the value of an object could not be parsed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObjectErrorKind {
+    /// The value does not have the format of the object.
+    Malformed,
+    /// A number is missing, contains other characters or is out of range.
+    InvalidNumber,
+    /// The unit is missing or not a known unit.
+    InvalidUnit,
+    /// The unit is known, but not a unit of the quantity of the object.
+    UnexpectedUnit,
+    /// A timestamp is not formatted as YYMMDDhhmmssX, or does not exist in the time zone.
+    InvalidTimestamp,
+    /// An octet string is not an even number of hex digits.
+    InvalidHex,
+}
+
+impl std::fmt::Display for ObjectErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ObjectErrorKind::Malformed => write!(f, "malformed value"),
+            ObjectErrorKind::InvalidNumber => write!(f, "invalid number"),
+            ObjectErrorKind::InvalidUnit => write!(f, "invalid unit"),
+            ObjectErrorKind::UnexpectedUnit => write!(f, "unexpected unit"),
+            ObjectErrorKind::InvalidTimestamp => write!(f, "invalid timestamp"),
+            ObjectErrorKind::InvalidHex => write!(f, "invalid hex string"),
+        }
+    }
+}
+
+/// Version of the P1 protocol used by the meter.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum Protocol {
+    /// DSMR 4 and 5, with a checksum in the footer.
+    #[default]
+    Dsmr4,
+    /// DSMR 2.2 and 3.0, without a checksum and with the gas reading in the old format.
+    Dsmr2,
+}
+
+/// How to handle objects that can not be parsed.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum ParseMode {
+    /// Reject the whole telegram.
+    #[default]
+    Strict,
+    /// Skip the object and keep the rest of the telegram. The skipped objects are available
+    /// on the data frame.
+    Lenient,
+}
+
+/// Parser of objects that are not supported by the parser itself, such as vendor-specific
+/// objects. Handlers are registered with [FrameParser::with_handler].
+pub trait ObjectHandler: Send + Sync {
+    /// Whether the handler parses objects with this OBIS code.
+    fn handles(&self, obis: ObisCode) -> bool;
+
+    /// Parse the value of an object, including its parentheses, e.g. `(001.234*kWh)`.
+    /// Timestamps are in time zone `tz`.
+    fn parse(&self, obis: ObisCode, value: &str, tz: Tz) -> Result<Object, ObjectErrorKind>;
+}
+
+pub struct FrameParser {
+    protocol: Protocol,
+    mode: ParseMode,
+    time_zone: Tz,
+    handlers: Vec<Box<dyn ObjectHandler>>,
+}
+
+impl Default for FrameParser {
+    fn default() -> Self {
+        Self::new(Protocol::default())
+    }
 }
 
-pub struct FrameParser;
 impl FrameParser {
-    pub fn parse(raw_frame: RawFrame) -> Result<DataFrame, ParseError> {
-        match parse_frame(raw_frame.get_data()) {
-            Ok((_, data_frame)) => Ok(data_frame),
-            Err(_) => Err(ParseError::Invalid),
+    pub fn new(protocol: Protocol) -> Self {
+        Self {
+            protocol,
+            mode: ParseMode::default(),
+            time_zone: chrono_tz::Europe::Amsterdam,
+            handlers: Vec::new(),
+        }
+    }
+
+    /// Set the time zone the meter is in, used to resolve the timestamps in the telegrams.
+    /// This is Europe/Amsterdam by default.
+    pub fn with_time_zone(mut self, time_zone: Tz) -> Self {
+        self.time_zone = time_zone;
+        self
+    }
+
+    /// Set how objects that can not be parsed are handled. This is strict by default.
+    pub fn with_mode(mut self, mode: ParseMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Register a handler for objects the parser does not support. Handlers are tried in the
+    /// order they are registered and take precedence over the built-in objects.
+    pub fn with_handler(mut self, handler: impl ObjectHandler + 'static) -> Self {
+        self.handlers.push(Box::new(handler));
+        self
+    }
+
+    /// Parse a raw frame into a data frame. Frames with a checksum that does not match
+    /// their contents are rejected, also in lenient mode.
+    pub fn parse(&self, raw_frame: RawFrame) -> Result<DataFrame, ParseError> {
+        let input = raw_frame.get_data();
+        check_checksum(input)?;
+
+        self.parse_frame(input)
+    }
+
+    fn parse_frame(&self, input: &str) -> Result<DataFrame, ParseError> {
+        let header: fn(&str) -> IResult<&str, Header> = match self.protocol {
+            Protocol::Dsmr4 => header,
+            Protocol::Dsmr2 => legacy_header,
+        };
+        let (rest, h) = header(input).map_err(|_| ParseError::InvalidHeader)?;
+
+        // Objects start on the line after the empty line following the header
+        let (rest, objects, skipped) = self.content(rest, 3)?;
+        if rest.is_empty() {
+            return Err(ParseError::MissingFooter);
+        }
+
+        let (_, crc) = match self.protocol {
+            Protocol::Dsmr4 => map(footer, Some)(rest),
+            Protocol::Dsmr2 => legacy_footer(rest),
+        }.map_err(|_| ParseError::InvalidFooter)?;
+
+        // The checksum covers everything from the header up to and including the '!'
+        let signed = &input[..input.len() - rest.len() + 1];
+
+        Ok(DataFrame::new(
+            h.0,
+            h.2,
+            objects,
+            crc,
+            crc16(signed.as_bytes()),
+        ).with_baud_rate(h.1).with_skipped(skipped))
+    }
+
+    /// Parse the objects up to the footer, where `line` is the line number of the first object.
+    /// In lenient mode, objects that can not be parsed are skipped and returned separately.
+    fn content<'a>(&self, mut input: &'a str, mut line: usize) -> Result<(&'a str, Vec<TelegramObject>, Vec<ParseError>), ParseError> {
+        let mut objects = Vec::new();
+        let mut skipped = Vec::new();
+
+        while !input.is_empty() && !input.starts_with('!') {
+            let (rest, object, num_lines) = match raw_object(input) {
+                Ok((rest, (obis, value))) => {
+                    let object = self.object(obis, value)
+                        .map(|object| TelegramObject::new(obis, object))
+                        .map_err(|kind| ParseError::InvalidObject { line, obis, kind });
+                    (rest, object, 1 + value.matches("\r\n").count())
+                },
+                Err(_) => match input.find("\r\n") {
+                    Some(end) => (&input[end + 2..], Err(ParseError::InvalidLine { line }), 1),
+                    // The telegram was cut off halfway a line
+                    None => return Err(ParseError::MissingFooter),
+                },
+            };
+
+            match object {
+                Ok(object) => objects.push(object),
+                Err(e) if self.mode == ParseMode::Lenient => skipped.push(e),
+                Err(e) => return Err(e),
+            }
+
+            input = rest;
+            line += num_lines;
+        }
+
+        Ok((input, objects, skipped))
+    }
+
+    /// Parse an object with the first handler for its code, or else as a built-in object.
+    fn object(&self, obis: ObisCode, value: &str) -> Result<Object, ObjectErrorKind> {
+        match self.handlers.iter().find(|h| h.handles(obis)) {
+            Some(handler) => handler.parse(obis, value, self.time_zone),
+            None => to_object(obis, value, self.time_zone),
         }
     }
 }
 
-fn parse_frame(input: &str) -> IResult<&str, DataFrame> {
-    let (input, (h, objects, crc)) = tuple((header, content, footer))(input)?;
+/// Check the checksum in the footer against the raw telegram, so no objects are parsed from
+/// corrupted data. Telegrams without a checksum in the footer pass.
+fn check_checksum(input: &str) -> Result<(), ParseError> {
+    let end = match input.rfind('!') {
+        Some(end) => end,
+        None => return Ok(()),
+    };
 
-    Ok((input, DataFrame::new(
-        h.0,
-        h.1,
-        objects,
-        crc,
-    )))
+    match footer(&input[end..]) {
+        Ok(("", expected)) => {
+            let calculated = crc16(&input.as_bytes()[..=end]);
+            if expected != calculated {
+                return Err(ParseError::ChecksumMismatch { expected, calculated });
+            }
+            Ok(())
+        }
+        _ => Ok(()),
+    }
 }
 
-/// Returns identifier
-fn header(input: &str) -> IResult<&str, (String, String)> {
+/// Prefix, baud rate character and identifier of a telegram header
+type Header = (String, char, String);
+
+fn header(input: &str) -> IResult<&str, Header> {
     let is_alphanumeric = |c: char| c.is_alphanumeric();
     let prefix = take_while_m_n(3, 3, is_alphanumeric);
     let ident = take_till(|c| c == '\r');
 
-    let (input, (_, p, _, i, _, _)) =
+    let (input, (_, p, b, i, _, _)) =
         tuple((char('/'), prefix, char('5'), ident, crlf, crlf))(input)?;
 
-    let result = (String::from(p), String::from(i));
+    let result = (String::from(p), b, String::from(i));
+    Ok((input, result))
+}
+
+/// Header of DSMR 2.2 and 3.0 telegrams, where the baud rate character can be anything.
+fn legacy_header(input: &str) -> IResult<&str, Header> {
+    let is_alphanumeric = |c: char| c.is_alphanumeric();
+    let prefix = take_while_m_n(3, 3, is_alphanumeric);
+    let ident = take_till(|c| c == '\r');
+
+    let (input, (_, p, b, i, _, _)) =
+        tuple((char('/'), prefix, satisfy(is_alphanumeric), ident, crlf, crlf))(input)?;
+
+    let result = (String::from(p), b, String::from(i));
     Ok((input, result))
 }
 
@@ -50,26 +290,22 @@ fn from_hex(input: &str) -> Result<u16, std::num::ParseIntError> {
     u16::from_str_radix(input, 16)
 }
 
-fn decimal(input: &str) -> IResult<&str, i64> {
-    map_res(
-        recognize(
-            many1(
-                one_of("0123456789")
-            )
+fn decimal(input: &str) -> ValueResult<'_, i64> {
+    invalid(ObjectErrorKind::InvalidNumber, map_res(
+        verify(
+            take_till1(|c| c == ')'),
+            |out: &str| out.chars().all(|c| c.is_ascii_digit())
         ),
-        |out: &str| i64::from_str_radix(out, 10)
-    )(input)
+        |out: &str| out.parse::<i64>()
+    ))(input)
 }
 
-fn decimal_point(input: &str) -> IResult<&str, f64> {
-    map_res(
-        recognize(
-            many1(
-                one_of("0123456789.")
-            )
-        ),
-        |out: &str| out.parse::<f64>()
-    )(input)
+/// A decimal number, which ends at the unit or the end of the value.
+fn decimal_point(input: &str) -> ValueResult<'_, Decimal> {
+    invalid(ObjectErrorKind::InvalidNumber, map_opt(
+        take_till1(|c| c == '*' || c == ')'),
+        Decimal::parse
+    ))(input)
 }
 
 fn crc_format(input: &str) -> IResult<&str, u16> {
@@ -85,27 +321,60 @@ fn footer(input: &str) -> IResult<&str, u16> {
     Ok((input, crc))
 }
 
-fn object(input: &str) -> IResult<&str, Object> {
-    // Must not start with ! as that is the footer
-    peek(not(char('!')))(input)?;
-
-    let (input, (object, _)) =
-        tuple((
-            map_res(
-                tuple((
-                          take_till(|c| c == '('),
-                          take_till(|c| c == '\r')
-                      )),
-                to_object
-            ),
-            crlf
-        ))(input)?;
+/// Footer of DSMR 2.2 and 3.0 telegrams, which is only a "!\r\n". Meters that do send a
+/// checksum are accepted too.
+fn legacy_footer(input: &str) -> IResult<&str, Option<u16>> {
+    let (input, (_, crc, _)) = tuple((char('!'), opt(crc_format), crlf))(input)?;
+    Ok((input, crc))
+}
+
+/// Split off an object as its OBIS reference and value. Values can continue on the next lines,
+/// which then start with a '('.
+fn raw_object(input: &str) -> IResult<&str, (ObisCode, &str)> {
+    let value = recognize(tuple((
+        char('('),
+        take_till(|c| c == '\r'),
+        many0(tuple((crlf, char('('), take_till(|c| c == '\r')))),
+    )));
 
-    Ok((input, object))
+    let (input, (obis, value, _)) =
+        tuple((map_opt(take_till1(|c| c == '(' || c == '\r'), ObisCode::parse), value, crlf))(input)?;
+
+    Ok((input, (obis, value)))
+}
+
+/// Error of the parser of an object value, with the reason the value is invalid.
+#[derive(Debug, PartialEq)]
+struct ValueError<'a> {
+    input: &'a str,
+    kind: ObjectErrorKind,
+}
+
+impl<'a> nom::error::ParseError<&'a str> for ValueError<'a> {
+    fn from_error_kind(input: &'a str, _: ErrorKind) -> Self {
+        Self { input, kind: ObjectErrorKind::Malformed }
+    }
+
+    fn append(_: &'a str, _: ErrorKind, other: Self) -> Self {
+        other
+    }
 }
 
-fn content(input: &str) -> IResult<&str, Vec<Object>> {
-    many1(object)(input)
+impl<'a, E> FromExternalError<&'a str, E> for ValueError<'a> {
+    fn from_external_error(input: &'a str, kind: ErrorKind, _: E) -> Self {
+        nom::error::ParseError::from_error_kind(input, kind)
+    }
+}
+
+type ValueResult<'a, O> = IResult<&'a str, O, ValueError<'a>>;
+
+/// Fail with the given reason when the parser fails.
+fn invalid<'a, O>(kind: ObjectErrorKind, mut parser: impl FnMut(&'a str) -> ValueResult<'a, O>) -> impl FnMut(&'a str) -> ValueResult<'a, O> {
+    move |input| parser(input).map_err(|e| e.map(|e| ValueError { kind, ..e }))
+}
+
+fn fail<O>(input: &str, kind: ObjectErrorKind) -> ValueResult<'_, O> {
+    Err(nom::Err::Error(ValueError { input, kind }))
 }
 
 //////// Objects
@@ -113,89 +382,389 @@ fn content(input: &str) -> IResult<&str, Vec<Object>> {
 // TST
 // YYMMDDhhmmssX
 // ASCII presentation of Time stamp with Year, Month, Day, Hour, Minute, Second, and an indication whether DST is active (X=S) or DST is not active (X=W).
-fn object_tst(input: &str) -> IResult<&str, DateTime<Local>> {
-    let (input, (_, str, _timezone, _)) = tuple((
-        char('('),
-        take_while_m_n(12, 12, |c: char| c.is_numeric()),
-        is_a("SW"),
-        char(')')
-        ))(input)?;
+fn object_tst(tz: Tz) -> impl Fn(&str) -> ValueResult<'_, DateTime<FixedOffset>> {
+    move |input| {
+        let (input, (_, (str, dst), _)) = tuple((
+            char('('),
+            invalid(ObjectErrorKind::InvalidTimestamp, tuple((
+                take_while_m_n(12, 12, |c: char| c.is_numeric()),
+                one_of("SW"),
+            ))),
+            char(')')
+            ))(input)?;
 
-    // TODO timezone. W = winter, S = summer
-    // println!("TIMEZONE {:?}", timezone);
+        match timestamp(str, Some(dst == 'S'), tz) {
+            Some(time) => Ok((input, time)),
+            None => fail(input, ObjectErrorKind::InvalidTimestamp),
+        }
+    }
+}
+
+/// Timestamp that can be the placeholder for 'no time' that Fluvius meters send for empty
+/// entries.
+fn object_optional_tst(tz: Tz) -> impl Fn(&str) -> ValueResult<'_, Option<DateTime<FixedOffset>>> {
+    move |input| alt((
+        map(tag("(632525252525W)"), |_| None),
+        map(object_tst(tz), Some),
+    ))(input)
+}
 
-    let time = Local.datetime_from_str(str, "%y%m%d%H%M%S")
-        .expect("Date time is not valid"); // TODO: handle error gracefully
+/// Resolve a local timestamp of the meter into an exact time. When the clocks go back, the
+/// hour before the change happens twice and the DST flag tells which one is meant. Without the
+/// flag, the first occurrence is used.
+fn timestamp(input: &str, dst: Option<bool>, tz: Tz) -> Option<DateTime<FixedOffset>> {
+    let naive = NaiveDateTime::parse_from_str(input, "%y%m%d%H%M%S").ok()?;
 
-    Ok((input, time))
+    let time = match tz.from_local_datetime(&naive) {
+        LocalResult::Single(time) => time,
+        LocalResult::Ambiguous(first, second) => {
+            let first_is_dst = !first.offset().dst_offset().is_zero();
+            if dst == Some(!first_is_dst) { second } else { first }
+        },
+        // Does not exist, as the clocks went forward
+        LocalResult::None => return None,
+    };
+
+    Some(time.with_timezone(&time.offset().fix()))
 }
 
-fn object_version(input: &str) -> IResult<&str, u32> {
-    let (input, (_, version, _)) = tuple((
+/// Parse an object that is (decimal), without a unit.
+fn object_integer(input: &str) -> ValueResult<'_, u32> {
+    let (input, (_, value, _)) = tuple((
         char('('),
         decimal,
         char(')'),
         ))(input)?;
 
-    Ok((input, version as u32))
+    match u32::try_from(value) {
+        Ok(value) => Ok((input, value)),
+        Err(_) => fail(input, ObjectErrorKind::InvalidNumber),
+    }
+}
+
+/// Parse an object that is (decimal*unit), where decimal can have a point and the unit must
+/// be one of the expected units.
+fn object_measurement(expected: &'static [Unit]) -> impl Fn(&str) -> ValueResult<'_, Measurement> {
+    move |input| {
+        let (input, measurement) = object_decimal_with_unit(input)?;
+
+        if !expected.contains(&measurement.unit) {
+            return fail(input, ObjectErrorKind::UnexpectedUnit);
+        }
+
+        Ok((input, measurement))
+    }
 }
 
-/// Parse an object that is (decimal*unit), where decimal can have a point.
-fn object_decimal_unit(input: &str) -> IResult<&str, f64> {
-    let (input, (_, value, _, _, _)) = tuple((
+/// Parse an object that is (decimal*unit) with any known unit.
+fn object_decimal_with_unit(input: &str) -> ValueResult<'_, Measurement> {
+    let (input, (_, value, (_, unit, _))) = tuple((
         char('('),
         decimal_point,
-        char('*'),
-        alphanumeric0,
-        char(')'),
+        invalid(ObjectErrorKind::InvalidUnit, tuple((
+            char('*'),
+            map_opt(alphanumeric1, Unit::parse),
+            char(')'),
+        ))),
     ))(input)?;
 
-    Ok((input, value))
+    Ok((input, Measurement::new(value, unit)))
 }
 
-fn object_gas(input: &str) -> IResult<&str, (DateTime<Local>, f64)> {
-    tuple((object_tst, object_decimal_unit))(input)
+/// Maximum number of events in the power failure event log.
+const MAX_POWER_FAILURE_EVENTS: u32 = 10;
+
+/// Parse the power failure event log with format (count)(0-0:96.7.19)(end)(duration*s)...
+fn object_power_failure_log(tz: Tz) -> impl Fn(&str) -> ValueResult<'_, Vec<PowerFailureEvent>> {
+    move |input| {
+        // Some meters send an empty count when there are no events
+        let (input, num_events) = alt((object_integer, map(tag("()"), |_| 0)))(input)?;
+        if num_events > MAX_POWER_FAILURE_EVENTS {
+            return fail(input, ObjectErrorKind::InvalidNumber);
+        }
+        let (input, _) = opt(tuple((char('('), take_till(|c| c == ')'), char(')'))))(input)?;
+
+        let event = map(
+            tuple((object_tst(tz), object_measurement(DURATION))),
+            |(end, duration)| PowerFailureEvent {
+                end,
+                duration: Duration::from_secs(duration.value.to_f64() as u64),
+            }
+        );
+
+        count(event, num_events as usize)(input)
+    }
 }
 
-/// Parse an object.
-fn to_object(input: (&str, &str)) -> Result<Object, ParseError> {
-    fn unwrap_parser<T>(r: IResult<&str, T>) -> Result<T, ParseError> {
-        match r {
-            Err(_) => Err(ParseError::Invalid),
-            Ok(v) => Ok(v.1),
+/// Parse an object that is (hex), an octet string with hex encoded characters.
+fn object_hex_string(input: &str) -> ValueResult<'_, String> {
+    let (input, (_, (hex, _))) = tuple((
+        char('('),
+        invalid(ObjectErrorKind::InvalidHex, tuple((
+            take_while_m_n(0, 4096, |c: char| c.is_hex_digit()),
+            char(')'),
+        ))),
+    ))(input)?;
+
+    let bytes = match decode_hex(hex) {
+        Some(bytes) => bytes,
+        None => return fail(input, ObjectErrorKind::InvalidHex),
+    };
+
+    Ok((input, String::from_utf8_lossy(&bytes).trim_end_matches('\0').to_string()))
+}
+
+/// Decode a string of hex digits, like an octet string or a key.
+pub fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+/// Parse a demand peak with format (timestamp)(value*kW)
+fn object_demand_peak(tz: Tz) -> impl Fn(&str) -> ValueResult<'_, DemandPeak> {
+    move |input| {
+        let (input, (time, value)) = tuple((object_tst(tz), object_measurement(POWER)))(input)?;
+
+        Ok((input, DemandPeak { time, value }))
+    }
+}
+
+/// Maximum number of months in the history of demand peaks.
+const MAX_DEMAND_PEAKS: u32 = 13;
+
+/// Parse the history of monthly demand peaks with format
+/// (count)(1-0:1.6.0)(1-0:1.6.0)(month start)(peak time)(value*kW)...
+fn object_demand_peak_history(tz: Tz) -> impl Fn(&str) -> ValueResult<'_, Vec<MonthlyDemandPeak>> {
+    move |input| {
+        let obis = |input| delimited(char('('), take_till(|c| c == ')'), char(')'))(input);
+
+        let (input, (num_peaks, _, _)) = tuple((object_integer, obis, obis))(input)?;
+        if num_peaks > MAX_DEMAND_PEAKS {
+            return fail(input, ObjectErrorKind::InvalidNumber);
+        }
+
+        let peak = map(
+            tuple((object_optional_tst(tz), object_optional_tst(tz), object_measurement(POWER))),
+            |(month, time, value)| MonthlyDemandPeak { month, time, value }
+        );
+
+        count(peak, num_peaks as usize)(input)
+    }
+}
+
+/// Parse the reading of an M-Bus device in the DSMR 2.2 and 3.0 format, with the value on the
+/// next line: (timestamp)(status)(period)(count)(obis)(unit)\r\n(value)
+fn object_legacy_mbus_reading(tz: Tz, expected: &'static [Unit]) -> impl Fn(&str) -> ValueResult<'_, MBusReading> {
+    move |input| {
+        let value = |input| delimited(char('('), take_till(|c| c == ')'), char(')'))(input);
+
+        let (input, (_, time, dst, _, _, _, unit, _, value)) = tuple((
+            char('('),
+            take_while_m_n(12, 12, |c: char| c.is_numeric()),
+            opt(one_of("SW")),
+            char(')'),
+            count(value, 3),
+            value,
+            invalid(ObjectErrorKind::InvalidUnit, map_opt(value, Unit::parse)),
+            crlf,
+            delimited(char('('), decimal_point, char(')')),
+        ))(input)?;
+
+        let time = match timestamp(time, dst.map(|dst| dst == 'S'), tz) {
+            Some(time) => time,
+            None => return fail(input, ObjectErrorKind::InvalidTimestamp),
+        };
+
+        if !expected.contains(&unit) {
+            return fail(input, ObjectErrorKind::UnexpectedUnit);
         }
+
+        Ok((input, MBusReading {
+            time,
+            value,
+            unit,
+        }))
+    }
+}
+
+/// Parse the reading of an M-Bus device with format (timestamp)(value*unit)
+fn object_mbus_reading(tz: Tz, expected: &'static [Unit]) -> impl Fn(&str) -> ValueResult<'_, MBusReading> {
+    move |input| {
+        let (input, (time, measurement)) = tuple((object_tst(tz), object_measurement(expected)))(input)?;
+
+        Ok((input, MBusReading {
+            time,
+            value: measurement.value,
+            unit: measurement.unit,
+        }))
+    }
+}
+
+/// Parse an integer value like `(00004)`, for use in an [ObjectHandler].
+pub fn parse_integer(value: &str) -> Result<u32, ObjectErrorKind> {
+    unwrap_parser(object_integer(value))
+}
+
+/// Parse a value with a unit like `(001.234*kWh)`, for use in an [ObjectHandler]. The unit
+/// must be one of `expected`.
+pub fn parse_measurement(value: &str, expected: &'static [Unit]) -> Result<Measurement, ObjectErrorKind> {
+    unwrap_parser(object_measurement(expected)(value))
+}
+
+/// Parse a hex encoded text like `(4D65746572)`, for use in an [ObjectHandler].
+pub fn parse_text(value: &str) -> Result<String, ObjectErrorKind> {
+    unwrap_parser(object_hex_string(value))
+}
+
+/// Parse a timestamp like `(101209113020W)` in time zone `tz`, for use in an [ObjectHandler].
+pub fn parse_timestamp(value: &str, tz: Tz) -> Result<DateTime<FixedOffset>, ObjectErrorKind> {
+    unwrap_parser(object_tst(tz)(value))
+}
+
+fn unwrap_parser<T>(r: ValueResult<'_, T>) -> Result<T, ObjectErrorKind> {
+    match r {
+        Err(nom::Err::Error(e) | nom::Err::Failure(e)) => Err(e.kind),
+        Err(nom::Err::Incomplete(_)) => Err(ObjectErrorKind::Malformed),
+        Ok(v) => Ok(v.1),
     }
+}
+
+/// Get the phase of a per-phase OBIS code. The C group is 2x/3x for L1, 4x/5x for L2 and 6x/7x for L3.
+fn phase_of(obis: ObisCode) -> Phase {
+    match obis.c / 10 {
+        2 | 3 => Phase::L1,
+        4 | 5 => Phase::L2,
+        _ => Phase::L3,
+    }
+}
+
+/// Parse an object.
+fn to_object(obis: ObisCode, value: &str, tz: Tz) -> Result<Object, ObjectErrorKind> {
+    // Units of values are checked against the registry
+    let units = obis.info().map_or(&[][..], |info| info.units);
 
-    let object = match input.0 {
-        "1-3:0.2.8" => {
-            let version = unwrap_parser(object_version(input.1))?;
+    let object = match (obis.a, obis.b, obis.c, obis.d, obis.e) {
+        (0, 0, 96, 1, 1) => {
+            let id = unwrap_parser(object_hex_string(value))?;
+            Object::EquipmentIdentifier(id)
+        },
+        (0, 0, 96, 13, 0) => {
+            let message = unwrap_parser(object_hex_string(value))?;
+            Object::TextMessage(message)
+        },
+        // e-MUCS meters send the identifier in 96.1.1
+        (0, channel @ 1..=4, 96, 1, 0 | 1) => {
+            let id = unwrap_parser(object_hex_string(value))?;
+            Object::MBusEquipmentIdentifier(channel, id)
+        },
+        (0, channel @ 1..=4, 24, 1, 0) => {
+            let code = unwrap_parser(object_integer(value))?;
+            Object::MBusDeviceType(channel, MBusDeviceType::from_code(code))
+        },
+        // e-MUCS meters send the gas reading without temperature correction in 24.2.3
+        (0, channel @ 1..=4, 24, 2, 1 | 3) => {
+            let reading = unwrap_parser(object_mbus_reading(tz, units)(value))?;
+            Object::MBusReading(channel, reading)
+        },
+        (0, channel @ 1..=4, 24, 3, 0) => {
+            let reading = unwrap_parser(object_legacy_mbus_reading(tz, units)(value))?;
+            Object::MBusReading(channel, reading)
+        },
+        (1, 3, 0, 2, 8) => {
+            let version = unwrap_parser(object_integer(value))?;
             Object::Version(version)
         },
-        "0-0:1.0.0" => {
-            let time = unwrap_parser(object_tst(input.1))?;
+        (0, 0, 1, 0, 0) => {
+            let time = unwrap_parser(object_tst(tz)(value))?;
             Object::Time(time)
         },
-        "1-0:1.8.1" => {
-            let value = unwrap_parser(object_decimal_unit(input.1))?;
+        (1, 0, 1, 8, 1) => {
+            let value = unwrap_parser(object_measurement(units)(value))?;
             Object::ElectricityDeliveredT1(value)
         },
-        "1-0:1.8.2" => {
-            let value = unwrap_parser(object_decimal_unit(input.1))?;
+        (1, 0, 1, 8, 2) => {
+            let value = unwrap_parser(object_measurement(units)(value))?;
             Object::ElectricityDeliveredT2(value)
         },
-        "1-0:1.7.0" => {
-            let value = unwrap_parser(object_decimal_unit(input.1))?;
+        (1, 0, 2, 8, 1) => {
+            let value = unwrap_parser(object_measurement(units)(value))?;
+            Object::ElectricityReceivedT1(value)
+        },
+        (1, 0, 2, 8, 2) => {
+            let value = unwrap_parser(object_measurement(units)(value))?;
+            Object::ElectricityReceivedT2(value)
+        },
+        (0, 0, 96, 14, 0) => {
+            let tariff = unwrap_parser(object_integer(value))?;
+            Object::TariffIndicator(tariff)
+        },
+        (1, 0, 1, 7, 0) => {
+            let value = unwrap_parser(object_measurement(units)(value))?;
             Object::ElectricityDelivering(value)
         },
-        "1-0:2.7.0" => {
-            let value = unwrap_parser(object_decimal_unit(input.1))?;
+        (1, 0, 2, 7, 0) => {
+            let value = unwrap_parser(object_measurement(units)(value))?;
             Object::ElectricityReceiving(value)
         },
-        "0-1:24.2.1" => {
-            let (time, value) = unwrap_parser(object_gas(input.1))?;
-            Object::GasDelivered(time, value)
+        (1, 0, 32 | 52 | 72, 7, 0) => {
+            let value = unwrap_parser(object_measurement(units)(value))?;
+            Object::Voltage(phase_of(obis), value)
+        },
+        (1, 0, 31 | 51 | 71, 7, 0) => {
+            let value = unwrap_parser(object_measurement(units)(value))?;
+            Object::Current(phase_of(obis), value)
+        },
+        (1, 0, 21 | 41 | 61, 7, 0) => {
+            let value = unwrap_parser(object_measurement(units)(value))?;
+            Object::PhaseDelivering(phase_of(obis), value)
+        },
+        (1, 0, 22 | 42 | 62, 7, 0) => {
+            let value = unwrap_parser(object_measurement(units)(value))?;
+            Object::PhaseReceiving(phase_of(obis), value)
+        },
+        (1, 0, 32 | 52 | 72, 32, 0) => {
+            let value = unwrap_parser(object_integer(value))?;
+            Object::VoltageSags(phase_of(obis), value)
+        },
+        (1, 0, 32 | 52 | 72, 36, 0) => {
+            let value = unwrap_parser(object_integer(value))?;
+            Object::VoltageSwells(phase_of(obis), value)
+        },
+        (0, 0, 96, 1, 4) => {
+            let version = unwrap_parser(object_integer(value))?;
+            Object::EmucsVersion(version)
         },
-        _ => Object::Unknown(input.0.to_string(), input.1.to_string()),
+        (1, 0, 1, 4, 0) => {
+            let value = unwrap_parser(object_measurement(units)(value))?;
+            Object::AverageDemand(value)
+        },
+        (1, 0, 1, 6, 0) => {
+            let peak = unwrap_parser(object_demand_peak(tz)(value))?;
+            Object::MaximumDemand(peak)
+        },
+        (0, 0, 98, 1, 0) => {
+            let peaks = unwrap_parser(object_demand_peak_history(tz)(value))?;
+            Object::MaximumDemandHistory(peaks)
+        },
+        (0, 0, 96, 7, 21) => {
+            let value = unwrap_parser(object_integer(value))?;
+            Object::PowerFailures(value)
+        },
+        (0, 0, 96, 7, 9) => {
+            let value = unwrap_parser(object_integer(value))?;
+            Object::LongPowerFailures(value)
+        },
+        (1, 0, 99, 97, 0) => {
+            let events = unwrap_parser(object_power_failure_log(tz)(value))?;
+            Object::PowerFailureLog(events)
+        },
+        _ => Object::Unknown(obis, value.to_string()),
     };
 
     Ok(object)
@@ -204,10 +773,28 @@ fn to_object(input: (&str, &str)) -> Result<Object, ParseError> {
 
 #[cfg(test)]
 mod tests {
-    use chrono::{Local, TimeZone};
-    use crate::data_frame::{Object, RawFrame};
-    use crate::Parser;
-    use crate::parser::{header, footer, content, object_gas};
+    use chrono::{FixedOffset, TimeZone, Utc};
+    use chrono_tz::Tz;
+    use crate::data_frame::{CustomValue, DataFrame, MBusDeviceType, Measurement, Object, Phase, RawFrame, TelegramObject, Unit};
+    use crate::decimal::Decimal;
+    use crate::obis::{ObisCode, MBUS};
+    use crate::parser::{header, footer, object_mbus_reading, object_power_failure_log, parse_integer, unwrap_parser, FrameParser, ObjectErrorKind, ObjectHandler, ParseError, ParseMode, Protocol};
+
+    const TZ: Tz = chrono_tz::Europe::Amsterdam;
+
+    fn dec(value: &str) -> Decimal {
+        Decimal::parse(value).unwrap()
+    }
+
+    /// Strictly parse the objects of a telegram without header and footer.
+    fn content(tz: Tz) -> impl Fn(&str) -> Result<(&str, Vec<Object>), ParseError> {
+        move |input| telegram_objects(tz)(input)
+            .map(|(rest, objects)| (rest, objects.into_iter().map(|o| o.object).collect()))
+    }
+
+    fn telegram_objects(tz: Tz) -> impl Fn(&str) -> Result<(&str, Vec<TelegramObject>), ParseError> {
+        move |input| FrameParser::default().with_time_zone(tz).content(input, 1).map(|(rest, objects, _)| (rest, objects))
+    }
 
     #[test]
     fn valid_header() {
@@ -215,7 +802,7 @@ mod tests {
 
         let res = header(input);
 
-        assert_eq!(res, Ok(("", (String::from("ISK"), String::from("\\2M550E-1012")))));
+        assert_eq!(res, Ok(("", (String::from("ISK"), '5', String::from("\\2M550E-1012")))));
     }
 
     #[test]
@@ -232,50 +819,416 @@ mod tests {
         let input = "/ISK5\\2M550E-1012\r\n\r\n1-3:0.2.8(50)\r\n0-0:1.0.0(211227133446W)\r\n0-0:96.1.1(4530303439303037343733383433363139)\r\n1-0:1.8.1(001382.570*kWh)\r\n1-0:1.8.2(001749.559*kWh)\r\n1-0:2.8.1(000000.000*kWh)\r\n1-0:2.8.2(000000.000*kWh)\r\n0-0:96.14.0(0002)\r\n1-0:1.7.0(00.200*kW)\r\n1-0:2.7.0(00.000*kW)\r\n0-0:96.7.21(00008)\r\n0-0:96.7.9(00003)\r\n1-0:99.97.0(2)(0-0:96.7.19)(190904052824S)(0000000293*s)(201115085142W)(0000006033*s)\r\n1-0:32.32.0(00006)\r\n1-0:32.36.0(00001)\r\n0-0:96.13.0()\r\n1-0:32.7.0(230.5*V)\r\n1-0:31.7.0(001*A)\r\n1-0:21.7.0(00.164*kW)\r\n1-0:22.7.0(00.000*kW)\r\n0-1:24.1.0(003)\r\n0-1:96.1.0(4730303634303032303039363134343230)\r\n0-1:24.2.1(211227133003W)(00409.167*m3)\r\n!38AF\r\n";
         let raw_frame = RawFrame::new(input.to_string());
 
-        let data_frame = Parser::parse(raw_frame).unwrap();
+        let data_frame = FrameParser::default().parse(raw_frame).unwrap();
+
+        assert!(data_frame.is_valid());
+        assert_eq!(data_frame.data.electricity_delivered_t1.to_string(), "1382.570");
+        assert_eq!(data_frame.data.l1.voltage, Some(230.5));
+        assert_eq!(data_frame.data.l1.current, Some(1.0));
+        assert_eq!(data_frame.data.l1.delivering, Some(0.164));
+        assert_eq!(data_frame.data.l1.receiving, Some(0.0));
+        assert_eq!(data_frame.data.l1.voltage_sags, Some(6));
+        assert_eq!(data_frame.data.l1.voltage_swells, Some(1));
+        assert!(!data_frame.data.l2.is_present());
+        assert!(!data_frame.data.l3.is_present());
+        assert_eq!(data_frame.data.power_failures, 8);
+        assert_eq!(data_frame.data.long_power_failures, 3);
+        assert_eq!(data_frame.data.power_failure_log.len(), 2);
+        assert_eq!(data_frame.meter_identity(), "E0049007473843619");
+        assert_eq!(data_frame.data.text_message, None);
+        assert_eq!(data_frame.data.mbus_devices.len(), 1);
+        assert_eq!(data_frame.data.mbus_devices[0].channel, 1);
+        assert_eq!(data_frame.data.mbus_devices[0].device_type, Some(MBusDeviceType::Gas));
+        assert_eq!(data_frame.data.mbus_devices[0].identifier.as_deref(), Some("G0064002009614420"));
+        assert_eq!(data_frame.data.gas_delivered, dec("409.167"));
+    }
+
+    #[test]
+    fn corrupted_frame_is_rejected() {
+        // Same telegram as above, with a single digit of T1 changed
+        let input = "/ISK5\\2M550E-1012\r\n\r\n1-3:0.2.8(50)\r\n0-0:1.0.0(211227133446W)\r\n0-0:96.1.1(4530303439303037343733383433363139)\r\n1-0:1.8.1(001382.571*kWh)\r\n1-0:1.8.2(001749.559*kWh)\r\n1-0:2.8.1(000000.000*kWh)\r\n1-0:2.8.2(000000.000*kWh)\r\n0-0:96.14.0(0002)\r\n1-0:1.7.0(00.200*kW)\r\n1-0:2.7.0(00.000*kW)\r\n0-0:96.7.21(00008)\r\n0-0:96.7.9(00003)\r\n1-0:99.97.0(2)(0-0:96.7.19)(190904052824S)(0000000293*s)(201115085142W)(0000006033*s)\r\n1-0:32.32.0(00006)\r\n1-0:32.36.0(00001)\r\n0-0:96.13.0()\r\n1-0:32.7.0(230.5*V)\r\n1-0:31.7.0(001*A)\r\n1-0:21.7.0(00.164*kW)\r\n1-0:22.7.0(00.000*kW)\r\n0-1:24.1.0(003)\r\n0-1:96.1.0(4730303634303032303039363134343230)\r\n0-1:24.2.1(211227133003W)(00409.167*m3)\r\n!38AF\r\n";
+        let raw_frame = RawFrame::new(input.to_string());
+
+        let res = FrameParser::default().parse(raw_frame);
+
+        assert!(matches!(res, Err(ParseError::ChecksumMismatch { expected: 0x38af, .. })));
+    }
+
+    #[test]
+    fn legacy_frame_parser() {
+        let input = "/ISk5\\2ME382-1003\r\n\r\n0-0:96.1.1(4B414C37303035313039333437313132)\r\n1-0:1.8.1(00185.000*kWh)\r\n1-0:1.8.2(00084.000*kWh)\r\n1-0:2.8.1(00000.000*kWh)\r\n1-0:2.8.2(00000.000*kWh)\r\n0-0:96.14.0(0001)\r\n1-0:1.7.0(0000.98*kW)\r\n1-0:2.7.0(0000.00*kW)\r\n0-0:17.0.0(999*A)\r\n0-0:96.3.10(1)\r\n0-0:96.13.1()\r\n0-0:96.13.0()\r\n0-1:24.1.0(3)\r\n0-1:96.1.0(3238313031353431303034303232323131)\r\n0-1:24.3.0(121030140000)(00)(60)(1)(0-1:24.2.1)(m3)\r\n(00004.164)\r\n0-1:24.4.0(1)\r\n!\r\n";
+        let parser = FrameParser::new(Protocol::Dsmr2);
+
+        let data_frame = parser.parse(RawFrame::new(input.to_string())).unwrap();
+
+        assert_eq!(data_frame.checksum(), None);
+        assert_eq!(data_frame.data.electricity_delivered_t1, dec("185.0"));
+        assert_eq!(data_frame.data.electricity_delivering, 0.98);
+        assert_eq!(data_frame.data.gas_delivered, dec("4.164"));
+        assert_eq!(data_frame.data.mbus_devices[0].reading.as_ref().unwrap().time, FixedOffset::east_opt(3600).unwrap().with_ymd_and_hms(2012, 10, 30, 14, 0, 0).unwrap());
+    }
+
+    #[test]
+    fn unknown_objects_are_kept() {
+        let input = "/ISk5\\2ME382-1003\r\n\r\n1-0:1.8.1(00185.000*kWh)\r\n0-0:17.0.0(999*A)\r\n0-0:96.3.10(1)\r\n1-0:1.8.2(00084.000*kWh)\r\n!\r\n";
+
+        let data_frame = FrameParser::new(Protocol::Dsmr2).parse(RawFrame::new(input.to_string())).unwrap();
+
+        let codes: Vec<String> = data_frame.objects().iter().map(|o| o.obis.to_string()).collect();
+        assert_eq!(codes, vec!["1-0:1.8.1", "0-0:17.0.0", "0-0:96.3.10", "1-0:1.8.2"]);
+        assert_eq!(data_frame.object(ObisCode::new(0, 0, 17, 0, 0)), Some(&Object::Unknown(ObisCode::new(0, 0, 17, 0, 0), String::from("(999*A)"))));
+        assert_eq!(data_frame.object(ObisCode::new(1, 0, 1, 8, 2)), Some(&Object::ElectricityDeliveredT2(Measurement::new(dec("84"), Unit::KWh))));
+        assert_eq!(data_frame.object(ObisCode::new(1, 0, 2, 8, 1)), None);
+    }
+
+    /// Handler of the vendor-specific counters 0-0:96.50.x.
+    struct VendorCounters;
+
+    impl ObjectHandler for VendorCounters {
+        fn handles(&self, obis: ObisCode) -> bool {
+            (obis.a, obis.b, obis.c, obis.d) == (0, 0, 96, 50)
+        }
+
+        fn parse(&self, obis: ObisCode, value: &str, _: Tz) -> Result<Object, ObjectErrorKind> {
+            Ok(Object::Custom(obis, CustomValue::Integer(parse_integer(value)?)))
+        }
+    }
+
+    #[test]
+    fn custom_objects_use_handler() {
+        let input = "/ISK5\\2M550E-1012\r\n\r\n0-0:96.50.1(00012)\r\n0-0:96.50.2(x)\r\n0-0:96.51.0(00007)\r\n!0000\r\n";
+        let parser = FrameParser::default().with_handler(VendorCounters).with_mode(ParseMode::Lenient);
+
+        let data_frame = parser.parse_frame(input).unwrap();
+
+        assert_eq!(data_frame.object(ObisCode::new(0, 0, 96, 50, 1)), Some(&Object::Custom(ObisCode::new(0, 0, 96, 50, 1), CustomValue::Integer(12))));
+        assert_eq!(data_frame.object(ObisCode::new(0, 0, 96, 51, 0)), Some(&Object::Unknown(ObisCode::new(0, 0, 96, 51, 0), String::from("(00007)"))));
+        assert_eq!(data_frame.skipped(), &[ParseError::InvalidObject {
+            line: 4,
+            obis: ObisCode::new(0, 0, 96, 50, 2),
+            kind: ObjectErrorKind::InvalidNumber,
+        }]);
+    }
+
+    #[test]
+    fn handler_overrides_builtin_object() {
+        struct RawTariff;
+
+        impl ObjectHandler for RawTariff {
+            fn handles(&self, obis: ObisCode) -> bool {
+                obis == ObisCode::new(0, 0, 96, 14, 0)
+            }
+
+            fn parse(&self, obis: ObisCode, value: &str, _: Tz) -> Result<Object, ObjectErrorKind> {
+                Ok(Object::Custom(obis, CustomValue::Text(value.to_string())))
+            }
+        }
+
+        let parser = FrameParser::default().with_handler(RawTariff);
+        let (_, objects, _) = parser.content("0-0:96.14.0(0002)\r\n", 1).unwrap();
+
+        assert_eq!(objects[0].object, Object::Custom(ObisCode::new(0, 0, 96, 14, 0), CustomValue::Text(String::from("(0002)"))));
+    }
+
+    #[test]
+    fn legacy_frame_needs_legacy_parser() {
+        let input = "/ISk5\\2ME382-1003\r\n\r\n1-0:1.8.1(00185.000*kWh)\r\n!\r\n";
+
+        assert_eq!(FrameParser::default().parse(RawFrame::new(input.to_string())).unwrap_err(), ParseError::InvalidFooter);
+        assert!(FrameParser::new(Protocol::Dsmr2).parse(RawFrame::new(input.to_string())).is_ok());
+    }
+
+    #[test]
+    fn emucs_frame_parser() {
+        let input = "/FLU5\\253769484_A\r\n\r\n0-0:96.1.4(50217)\r\n0-0:96.1.1(3153414733313031303231363035)\r\n0-0:1.0.0(200512135409S)\r\n1-0:1.8.1(000000.034*kWh)\r\n1-0:1.8.2(000015.758*kWh)\r\n1-0:2.8.1(000000.000*kWh)\r\n1-0:2.8.2(000000.011*kWh)\r\n1-0:1.4.0(02.351*kW)\r\n1-0:1.6.0(200509134558S)(02.589*kW)\r\n0-0:98.1.0(3)(1-0:1.6.0)(1-0:1.6.0)(200501000000S)(200423192538S)(03.695*kW)(200401000000S)(200305122139S)(05.980*kW)(200301000000S)(632525252525W)(00.000*kW)\r\n0-0:96.14.0(0001)\r\n1-0:1.7.0(00.000*kW)\r\n1-0:2.7.0(00.000*kW)\r\n0-0:96.13.0()\r\n0-1:24.1.0(003)\r\n0-1:96.1.1(37464C4F32313139303333373333)\r\n0-1:24.2.3(200512134558S)(00112.384*m3)\r\n!4B55\r\n";
+        let raw_frame = RawFrame::new(input.to_string());
+
+        let data_frame = FrameParser::default().parse(raw_frame).unwrap();
+
+        assert_eq!(data_frame.data.emucs_version, Some(50217));
+        assert_eq!(data_frame.data.average_demand, Some(2.351));
+        assert_eq!(data_frame.data.maximum_demand.as_ref().unwrap().value, Measurement::new(dec("2.589"), Unit::KW));
+        assert_eq!(data_frame.data.maximum_demand.as_ref().unwrap().time, FixedOffset::east_opt(7200).unwrap().with_ymd_and_hms(2020, 5, 9, 13, 45, 58).unwrap());
+
+        let history = &data_frame.data.maximum_demand_history;
+        assert_eq!(history.len(), 3);
+        assert_eq!(history[0].month, Some(FixedOffset::east_opt(7200).unwrap().with_ymd_and_hms(2020, 5, 1, 0, 0, 0).unwrap()));
+        assert_eq!(history[1].value, Measurement::new(dec("5.980"), Unit::KW));
+        assert_eq!(history[2].time, None);
+
+        assert_eq!(data_frame.data.mbus_devices[0].identifier.as_deref(), Some("7FLO2119033733"));
+        assert_eq!(data_frame.data.gas_delivered, dec("112.384"));
+    }
+
+    #[test]
+    fn only_placeholder_is_no_time() {
+        // 02:30 did not exist on 29 March 2020
+        for peak in ["(200329023000S)", "(2005010000S)", "()"] {
+            let input = format!("0-0:98.1.0(1)(1-0:1.6.0)(1-0:1.6.0)(200301000000W){}(03.695*kW)\r\n", peak);
+
+            assert!(matches!(
+                content(TZ)(&input),
+                Err(ParseError::InvalidObject { kind: ObjectErrorKind::InvalidTimestamp | ObjectErrorKind::Malformed, .. })
+            ));
+        }
+    }
 
-        assert_eq!(data_frame.is_valid(), true);
+    #[test]
+    fn too_many_demand_peaks() {
+        assert!(matches!(
+            content(TZ)("0-0:98.1.0(4294967295)(1-0:1.6.0)(1-0:1.6.0)(200501000000S)(200423192538S)(03.695*kW)\r\n"),
+            Err(ParseError::InvalidObject { kind: ObjectErrorKind::InvalidNumber, .. })
+        ));
     }
 
     #[test]
     fn time_object() {
         let input = "0-0:1.0.0(211227133446W)\r\n";
-        let date = Local.ymd(2021, 12, 27).and_hms(13,34,46);
+        let date = FixedOffset::east_opt(3600).unwrap().with_ymd_and_hms(2021, 12, 27, 13, 34, 46).unwrap();
 
-        let res = content(input).unwrap();
+        let res = content(TZ)(input).unwrap();
 
         assert_eq!(res.1.len(), 1);
         assert_eq!(res.1.first().unwrap().clone(), Object::Time(date));
     }
 
+    #[test]
+    fn ambiguous_time_uses_dst_flag() {
+        // Clocks went back at 03:00 CEST on 31 October 2021, so 02:30 happened twice
+        let summer = content(TZ)("0-0:1.0.0(211031023000S)\r\n").unwrap().1;
+        let winter = content(TZ)("0-0:1.0.0(211031023000W)\r\n").unwrap().1;
+
+        assert_eq!(summer, vec![Object::Time(FixedOffset::east_opt(7200).unwrap().with_ymd_and_hms(2021, 10, 31, 2, 30, 0).unwrap())]);
+        assert_eq!(winter, vec![Object::Time(FixedOffset::east_opt(3600).unwrap().with_ymd_and_hms(2021, 10, 31, 2, 30, 0).unwrap())]);
+        assert_eq!(winter[0].clone(), Object::Time(Utc.with_ymd_and_hms(2021, 10, 31, 1, 30, 0).unwrap().into()));
+    }
+
+    #[test]
+    fn nonexistent_time_is_rejected() {
+        // Clocks went forward at 02:00 CET on 28 March 2021
+        assert!(matches!(
+            content(TZ)("0-0:1.0.0(210328023000W)\r\n"),
+            Err(ParseError::InvalidObject { kind: ObjectErrorKind::InvalidTimestamp, .. })
+        ));
+    }
+
+    #[test]
+    fn time_in_other_zone() {
+        let res = content(chrono_tz::Europe::London)("0-0:1.0.0(211227133446W)\r\n").unwrap();
+
+        assert_eq!(res.1, vec![Object::Time(Utc.with_ymd_and_hms(2021, 12, 27, 13, 34, 46).unwrap().into())]);
+    }
+
     #[test]
     fn delivered_total_t1_object() {
         let input = "1-0:1.8.1(001382.570*kWh)\r\n";
 
-        let res = content(input).unwrap();
+        let res = content(TZ)(input).unwrap();
 
         assert_eq!(res.1.len(), 1);
-        assert_eq!(res.1.first().unwrap().clone(), Object::ElectricityDeliveredT1(1382.570));
+        assert_eq!(res.1.first().unwrap().clone(), Object::ElectricityDeliveredT1(Measurement::new(dec("1382.570"), Unit::KWh)));
+    }
+
+    #[test]
+    fn received_total_t2_object() {
+        let input = "1-0:2.8.2(000123.456*kWh)\r\n";
+
+        let res = content(TZ)(input).unwrap();
+
+        assert_eq!(res.1.len(), 1);
+        assert_eq!(res.1.first().unwrap().clone(), Object::ElectricityReceivedT2(Measurement::new(dec("123.456"), Unit::KWh)));
+    }
+
+    #[test]
+    fn tariff_object() {
+        let input = "0-0:96.14.0(0002)\r\n";
+
+        let res = content(TZ)(input).unwrap();
+
+        assert_eq!(res.1.len(), 1);
+        assert_eq!(res.1.first().unwrap().clone(), Object::TariffIndicator(2));
+    }
+
+    #[test]
+    fn phase_objects() {
+        let input = "1-0:52.7.0(231.2*V)\r\n1-0:71.7.0(002*A)\r\n1-0:42.7.0(00.012*kW)\r\n1-0:72.32.0(00004)\r\n1-0:52.36.0(00002)\r\n";
+
+        let res = content(TZ)(input).unwrap();
+
+        assert_eq!(res.1, vec![
+            Object::Voltage(Phase::L2, Measurement::new(dec("231.2"), Unit::V)),
+            Object::Current(Phase::L3, Measurement::new(dec("2.0"), Unit::A)),
+            Object::PhaseReceiving(Phase::L2, Measurement::new(dec("0.012"), Unit::KW)),
+            Object::VoltageSags(Phase::L3, 4),
+            Object::VoltageSwells(Phase::L2, 2),
+        ]);
     }
 
     #[test]
     fn delivered_object() {
         let input = "1-0:1.7.0(00.200*kW)\r\n";
 
-        let res = content(input).unwrap();
+        let res = content(TZ)(input).unwrap();
 
         assert_eq!(res.1.len(), 1);
-        assert_eq!(res.1.first().unwrap().clone(), Object::ElectricityDelivered(0.200));
+        assert_eq!(res.1.first().unwrap().clone(), Object::ElectricityDelivering(Measurement::new(dec("0.200"), Unit::KW)));
+    }
+
+    #[test]
+    fn power_failure_log() {
+        let input = "(2)(0-0:96.7.19)(190904052824S)(0000000293*s)(201115085142W)(0000006033*s)";
+
+        let (_, events) = object_power_failure_log(TZ)(input).unwrap();
+
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].end, FixedOffset::east_opt(7200).unwrap().with_ymd_and_hms(2019, 9, 4, 5, 28, 24).unwrap());
+        assert_eq!(events[0].duration.as_secs(), 293);
+        assert_eq!(events[1].end, FixedOffset::east_opt(3600).unwrap().with_ymd_and_hms(2020, 11, 15, 8, 51, 42).unwrap());
+        assert_eq!(events[1].duration.as_secs(), 6033);
+    }
+
+    #[test]
+    fn empty_power_failure_log() {
+        assert_eq!(object_power_failure_log(TZ)("(0)(0-0:96.7.19)").unwrap().1, vec![]);
+        assert_eq!(object_power_failure_log(TZ)("()(0-0:96.7.19)").unwrap().1, vec![]);
+    }
+
+    #[test]
+    fn too_many_power_failures() {
+        let input = "(4294967295)(0-0:96.7.19)(190904052824S)(0000000293*s)";
+
+        assert_eq!(unwrap_parser(object_power_failure_log(TZ)(input)), Err(ObjectErrorKind::InvalidNumber));
+    }
+
+    #[test]
+    fn text_message() {
+        let input = "0-0:96.13.0(48656C6C6F)\r\n";
+
+        let res = content(TZ)(input).unwrap();
+
+        assert_eq!(res.1, vec![Object::TextMessage(String::from("Hello"))]);
+    }
+
+    #[test]
+    fn odd_length_hex_string() {
+        assert!(matches!(
+            content(TZ)("0-0:96.1.1(453)\r\n"),
+            Err(ParseError::InvalidObject { kind: ObjectErrorKind::InvalidHex, .. })
+        ));
+    }
+
+    #[test]
+    fn invalid_object_has_location() {
+        let input = "/ISK5\\2M550E-1012\r\n\r\n1-3:0.2.8(50)\r\n1-0:1.8.1(0013x2.570*kWh)\r\n!0B24\r\n";
+
+        let res = FrameParser::default().parse(RawFrame::new(input.to_string()));
+
+        assert_eq!(res.unwrap_err(), ParseError::InvalidObject {
+            line: 4,
+            obis: ObisCode::new(1, 0, 1, 8, 1),
+            kind: ObjectErrorKind::InvalidNumber,
+        });
+    }
+
+    #[test]
+    fn checksum_is_checked_before_objects() {
+        // A corrupted digit is reported as corruption, not as an invalid number
+        let input = "/ISK5\\2M550E-1012\r\n\r\n1-3:0.2.8(50)\r\n1-0:1.8.1(0013x2.570*kWh)\r\n1-0:99.97.0(4294967295)(0-0:96.7.19)\r\n!0000\r\n";
+
+        let res = FrameParser::default().parse(RawFrame::new(input.to_string()));
+
+        assert!(matches!(res, Err(ParseError::ChecksumMismatch { expected: 0, .. })));
+    }
+
+    #[test]
+    fn invalid_unit() {
+        assert_eq!(content(TZ)("1-0:1.7.0(00.200)\r\n").unwrap_err(), ParseError::InvalidObject {
+            line: 1,
+            obis: ObisCode::new(1, 0, 1, 7, 0),
+            kind: ObjectErrorKind::InvalidUnit,
+        });
+    }
+
+    #[test]
+    fn unexpected_unit() {
+        assert_eq!(content(TZ)("1-0:32.7.0(230.5*kWh)\r\n").unwrap_err(), ParseError::InvalidObject {
+            line: 1,
+            obis: ObisCode::new(1, 0, 32, 7, 0),
+            kind: ObjectErrorKind::UnexpectedUnit,
+        });
+        assert!(matches!(
+            content(TZ)("1-0:32.7.0(230.5*mV)\r\n"),
+            Err(ParseError::InvalidObject { kind: ObjectErrorKind::InvalidUnit, .. })
+        ));
+    }
+
+    #[test]
+    fn units_are_normalized() {
+        let input = "1-0:1.8.1(001382570*Wh)\r\n1-0:1.7.0(200*W)\r\n1-0:21.7.0(00.164*kW)\r\n1-0:1.6.0(200509134558S)(2589*W)\r\n";
+
+        let (_, objects) = telegram_objects(TZ)(input).unwrap();
+        let data_frame = DataFrame::new(String::from("ISK"), String::new(), objects.clone(), None, 0);
+
+        assert_eq!(objects[0].object, Object::ElectricityDeliveredT1(Measurement::new(dec("1382570"), Unit::Wh)));
+        assert_eq!(data_frame.data.electricity_delivered_t1, dec("1382.570"));
+        assert_eq!(data_frame.data.electricity_delivering, 0.2);
+        assert_eq!(data_frame.data.l1.delivering, Some(0.164));
+        assert_eq!(data_frame.data.maximum_demand.unwrap().value, Measurement::new(dec("2.589"), Unit::KW));
+    }
+
+    #[test]
+    fn missing_footer() {
+        let input = "/ISK5\\2M550E-1012\r\n\r\n1-3:0.2.8(50)\r\n1-0:1.8.1(0013";
+
+        let res = FrameParser::default().parse(RawFrame::new(input.to_string()));
+
+        assert_eq!(res.unwrap_err(), ParseError::MissingFooter);
+    }
+
+    #[test]
+    fn lenient_mode_skips_invalid_objects() {
+        let input = "/ISk5\\2ME382-1003\r\n\r\n1-0:1.8.1(00185.000*kWh)\r\ngarbage\r\n1-0:1.8.2(000x4.000*kWh)\r\n1-0:2.8.1(00001.000*kWh)\r\n!\r\n";
+
+        let strict = FrameParser::new(Protocol::Dsmr2).parse(RawFrame::new(input.to_string()));
+        let lenient = FrameParser::new(Protocol::Dsmr2)
+            .with_mode(ParseMode::Lenient)
+            .parse(RawFrame::new(input.to_string()))
+            .unwrap();
+
+        assert_eq!(strict.unwrap_err(), ParseError::InvalidLine { line: 4 });
+        assert_eq!(lenient.data.electricity_delivered_t1, dec("185.0"));
+        assert_eq!(lenient.data.electricity_received_t1, dec("1.0"));
+        assert_eq!(lenient.skipped(), &[
+            ParseError::InvalidLine { line: 4 },
+            ParseError::InvalidObject { line: 5, obis: ObisCode::new(1, 0, 1, 8, 2), kind: ObjectErrorKind::InvalidNumber },
+        ]);
     }
 
     #[test]
     fn gas_value() {
         let input = "(211227133003W)(00409.167*m3)";
-        let date = Local.ymd(2021, 12, 27).and_hms(13,30,03);
+        let date = FixedOffset::east_opt(3600).unwrap().with_ymd_and_hms(2021, 12, 27, 13, 30, 3).unwrap();
+
+        let res = object_mbus_reading(TZ, MBUS)(input).unwrap();
+
+        assert_eq!(res.1.time, date);
+        assert_eq!(res.1.value, dec("409.167"));
+        assert_eq!(res.1.unit, Unit::M3);
+    }
+
+    #[test]
+    fn mbus_devices_on_other_channels() {
+        let input = "0-2:24.1.0(007)\r\n0-2:24.2.1(211227133000W)(00012.345*m3)\r\n0-3:24.1.0(003)\r\n0-3:24.2.1(211227133000W)(00409.167*m3)\r\n0-4:24.1.0(012)\r\n0-4:24.2.1(211227133000W)(00001.234*GJ)\r\n";
 
-        let res = object_gas(input).unwrap();
+        let (_, objects) = telegram_objects(TZ)(input).unwrap();
+        let data_frame = DataFrame::new(String::from("ISK"), String::new(), objects, None, 0);
 
-        assert_eq!(res.1.0, date);
-        assert_eq!(res.1.1, 409.167);
+        let devices = &data_frame.data.mbus_devices;
+        assert_eq!(devices.len(), 3);
+        assert_eq!(devices[0].device_type, Some(MBusDeviceType::Water));
+        assert_eq!(devices[1].device_type, Some(MBusDeviceType::Gas));
+        assert_eq!(devices[2].device_type, Some(MBusDeviceType::Heat));
+        assert_eq!(devices[2].reading.as_ref().unwrap().unit, Unit::GJ);
+        assert_eq!(data_frame.data.gas_delivered, dec("409.167"));
     }
 }
@@ -1,20 +1,32 @@
 use std::fs::File;
-use std::io::{BufReader, Write, Read};
+use std::io::{BufReader, Read};
 use std::io::ErrorKind::TimedOut;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use ringbuf::{Consumer, Producer, RingBuffer};
-use serialport::{DataBits, Parity, SerialPort, StopBits};
+use serialport::{DataBits, FlowControl, Parity, SerialPort, StopBits};
 
 pub struct PortBuilder;
 impl PortBuilder {
     pub fn from_path<P: AsRef<Path>>(path: P) -> Box<dyn Port> {
+        Self::from_path_with_buffer(path, BufferOptions::default())
+    }
+
+    /// Open a serial device with the given buffer options, or a file if the path is not a
+    /// serial device.
+    pub fn from_path_with_buffer<P: AsRef<Path>>(path: P, buffer: BufferOptions) -> Box<dyn Port> {
+        Self::from_path_with_settings(path, SerialSettings::default(), buffer)
+    }
+
+    /// Open a serial device with the given serial settings and buffer options, or a file if the
+    /// path is not a serial device.
+    pub fn from_path_with_settings<P: AsRef<Path>>(path: P, serial: SerialSettings, buffer: BufferOptions) -> Box<dyn Port> {
         let x = path.as_ref();
 
         if let Some(device) = PortBuilder::get_serial_devices()
             .iter()
-            .find(|p| *p == &x) {
-            Self::from_device(device.to_str().unwrap())
+            .find(|p| *p == x) {
+            Self::from_device_with_settings(device.to_str().unwrap(), serial, buffer)
         } else {
             Self::from_file(path)
         }
@@ -25,51 +37,362 @@ impl PortBuilder {
     }
 
     pub fn from_device<P: AsRef<Path>>(path: P) -> Box<dyn Port> {
-        Box::new(USBPort::new(path))
+        Self::from_device_with_buffer(path, BufferOptions::default())
+    }
+
+    pub fn from_device_with_buffer<P: AsRef<Path>>(path: P, buffer: BufferOptions) -> Box<dyn Port> {
+        Self::from_device_with_settings(path, SerialSettings::default(), buffer)
+    }
+
+    pub fn from_device_with_settings<P: AsRef<Path>>(path: P, serial: SerialSettings, buffer: BufferOptions) -> Box<dyn Port> {
+        Box::new(USBPort::new(path, serial, buffer))
+    }
+
+    /// Decrypt the output of a port, for meters with encrypted telegrams like the Luxembourg Smarty.
+    #[cfg(feature = "smarty")]
+    pub fn decrypting(port: Box<dyn Port>, key: [u8; 16], aad: Option<[u8; 16]>) -> Box<dyn Port> {
+        Box::new(crate::smarty::DecryptingPort::new(port, key, aad))
+    }
+
+    #[cfg(test)]
+    pub fn from_data(data: &[u8]) -> Box<dyn Port> {
+        Box::new(DataPort::new(data))
     }
 
     /// Get a list of port paths
     fn get_serial_devices() -> Vec<PathBuf> {
         serialport::available_ports()
-            .unwrap_or(Vec::new())
+            .unwrap_or_default()
             .iter()
             .flat_map(|p|  PathBuf::from_str(&p.port_name))
             .collect()
     }
 }
 
-pub trait Port {
+pub trait Port: Send {
     /// Fetch values from the data source into intermediate buffers, if needed.
     fn fetch(&mut self);
     /// Read a single byte.
     fn read(&mut self) -> Option<u8>;
+
+    /// Append the bytes that are available to `buffer`, and return how many were added.
+    fn read_available(&mut self, buffer: &mut Vec<u8>) -> usize {
+        let start = buffer.len();
+
+        while let Some(byte) = self.read() {
+            buffer.push(byte);
+        }
+
+        buffer.len() - start
+    }
+
+    /// Take where bytes were lost in the data returned by the last `read_available`, for
+    /// example because a buffer overflowed.
+    fn take_gaps(&mut self) -> Vec<Gap> {
+        Vec::new()
+    }
+
+    /// Whether the port will not return any more data, like a file that was read to the end.
+    /// Serial ports are never closed.
+    fn is_closed(&self) -> bool {
+        false
+    }
 }
 
-pub struct USBPort {
-    serialport: Box<dyn SerialPort>,
+/// Bytes that were lost from the output of a port.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Gap {
+    /// Number of bytes that were read before the lost bytes
+    pub position: usize,
+    /// Number of bytes that were lost
+    pub size: usize,
+}
+
+/// What to do with data from the serial port when the buffer is full.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum OverflowPolicy {
+    /// Drop the oldest data in the buffer, to keep the most recent telegrams.
+    DropOldest,
+    /// Drop the data that does not fit anymore.
+    #[default]
+    DropNewest,
+    /// Stop reading from the serial port until there is room. The data waits in the buffer
+    /// of the operating system, which can overflow in turn.
+    Block,
+}
+
+impl FromStr for OverflowPolicy {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "drop-oldest" => Ok(OverflowPolicy::DropOldest),
+            "drop-newest" => Ok(OverflowPolicy::DropNewest),
+            "block" => Ok(OverflowPolicy::Block),
+            _ => Err(format!("'{}' is not one of drop-oldest, drop-newest or block", input)),
+        }
+    }
+}
+
+/// Size and overflow policy of the buffer between a serial port and the frame reader.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct BufferOptions {
+    pub size: usize,
+    pub overflow: OverflowPolicy,
+}
+
+impl Default for BufferOptions {
+    fn default() -> Self {
+        Self {
+            size: 4096,
+            overflow: OverflowPolicy::default(),
+        }
+    }
+}
+
+/// Data bits, parity and stop bits of a serial connection, written like "8N1".
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct SerialFormat {
+    pub data_bits: DataBits,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+}
+
+impl SerialFormat {
+    /// Format of DSMR 4 and 5 meters
+    pub const DSMR4: SerialFormat = SerialFormat { data_bits: DataBits::Eight, parity: Parity::None, stop_bits: StopBits::One };
+    /// Format of DSMR 2.2 and 3 meters
+    pub const DSMR2: SerialFormat = SerialFormat { data_bits: DataBits::Seven, parity: Parity::Even, stop_bits: StopBits::One };
+}
+
+impl Default for SerialFormat {
+    fn default() -> Self {
+        Self::DSMR4
+    }
+}
+
+impl FromStr for SerialFormat {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let error = || format!("'{}' is not a serial format like 8N1 or 7E1", input);
+
+        let chars: Vec<char> = input.chars().collect();
+        let (data_bits, parity, stop_bits) = match chars[..] {
+            [data_bits, parity, stop_bits] => (data_bits, parity, stop_bits),
+            _ => return Err(error()),
+        };
+
+        Ok(SerialFormat {
+            data_bits: match data_bits {
+                '5' => DataBits::Five,
+                '6' => DataBits::Six,
+                '7' => DataBits::Seven,
+                '8' => DataBits::Eight,
+                _ => return Err(error()),
+            },
+            parity: match parity.to_ascii_uppercase() {
+                'N' => Parity::None,
+                'E' => Parity::Even,
+                'O' => Parity::Odd,
+                _ => return Err(error()),
+            },
+            stop_bits: match stop_bits {
+                '1' => StopBits::One,
+                '2' => StopBits::Two,
+                _ => return Err(error()),
+            },
+        })
+    }
+}
+
+impl std::fmt::Display for SerialFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let data_bits = match self.data_bits {
+            DataBits::Five => 5,
+            DataBits::Six => 6,
+            DataBits::Seven => 7,
+            DataBits::Eight => 8,
+        };
+        let parity = match self.parity {
+            Parity::None => 'N',
+            Parity::Even => 'E',
+            Parity::Odd => 'O',
+        };
+        let stop_bits = match self.stop_bits {
+            StopBits::One => 1,
+            StopBits::Two => 2,
+        };
+
+        write!(f, "{}{}{}", data_bits, parity, stop_bits)
+    }
+}
+
+/// Settings of a serial connection. The default are the settings of DSMR 4 and 5 meters,
+/// 115200 baud 8N1.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct SerialSettings {
+    pub baud_rate: u32,
+    pub format: SerialFormat,
+    pub flow_control: FlowControl,
+}
+
+impl SerialSettings {
+    pub const DSMR4: SerialSettings = SerialSettings { baud_rate: 115_200, format: SerialFormat::DSMR4, flow_control: FlowControl::None };
+    pub const DSMR2: SerialSettings = SerialSettings { baud_rate: 9600, format: SerialFormat::DSMR2, flow_control: FlowControl::None };
+}
+
+impl Default for SerialSettings {
+    fn default() -> Self {
+        Self::DSMR4
+    }
+}
+
+impl std::fmt::Display for SerialSettings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} baud {}", self.baud_rate, self.format)?;
+
+        match self.flow_control {
+            FlowControl::None => Ok(()),
+            FlowControl::Software => write!(f, ", software flow control"),
+            FlowControl::Hardware => write!(f, ", hardware flow control"),
+        }
+    }
+}
+
+/// Parse the flow control of a serial connection: none, software or hardware.
+pub fn parse_flow_control(input: &str) -> Result<FlowControl, String> {
+    match input {
+        "none" => Ok(FlowControl::None),
+        "software" => Ok(FlowControl::Software),
+        "hardware" => Ok(FlowControl::Hardware),
+        _ => Err(format!("'{}' is not one of none, software or hardware", input)),
+    }
+}
+
+/// Ring buffer that keeps track of the bytes it drops when it overflows.
+pub(crate) struct OverflowBuffer {
     producer: Producer<u8>,
     consumer: Consumer<u8>,
+    policy: OverflowPolicy,
+    /// Positions are relative to the oldest byte in the buffer
+    gaps: Vec<Gap>,
+}
+
+impl OverflowBuffer {
+    pub(crate) fn new(options: BufferOptions) -> Self {
+        let (producer, consumer) = RingBuffer::new(options.size).split();
+
+        Self {
+            producer,
+            consumer,
+            policy: options.overflow,
+            gaps: Vec::new(),
+        }
+    }
+
+    /// Number of bytes that can be added without overflowing.
+    fn remaining(&self) -> usize {
+        self.producer.remaining()
+    }
+
+    #[cfg(feature = "async")]
+    pub(crate) fn is_empty(&self) -> bool {
+        self.consumer.is_empty()
+    }
+
+    fn push(&mut self, data: &[u8]) {
+        let overflow = data.len().saturating_sub(self.producer.remaining());
+        if overflow == 0 {
+            self.producer.push_slice(data);
+            return;
+        }
+
+        match self.policy {
+            OverflowPolicy::DropOldest => {
+                // Data larger than the whole buffer only keeps its end
+                let skipped = data.len().saturating_sub(self.producer.capacity());
+                let discarded = self.consumer.discard(overflow - skipped);
+
+                for gap in &mut self.gaps {
+                    gap.position = gap.position.saturating_sub(discarded);
+                }
+                self.add_gap(0, discarded + skipped);
+                self.producer.push_slice(&data[skipped..]);
+            }
+            OverflowPolicy::DropNewest | OverflowPolicy::Block => {
+                let pushed = self.producer.push_slice(data);
+                self.add_gap(self.consumer.len(), data.len() - pushed);
+            }
+        }
+    }
+
+    /// Add data that already has gaps, e.g. the output of another port.
+    #[cfg(feature = "async")]
+    pub(crate) fn extend(&mut self, data: &[u8], gaps: &[Gap]) {
+        let mut start = 0;
+
+        for gap in gaps {
+            self.push(&data[start..gap.position]);
+            self.add_gap(self.consumer.len(), gap.size);
+            start = gap.position;
+        }
+
+        self.push(&data[start..]);
+    }
+
+    fn add_gap(&mut self, position: usize, size: usize) {
+        match self.gaps.iter_mut().find(|gap| gap.position == position) {
+            Some(gap) => gap.size += size,
+            None => self.gaps.push(Gap { position, size }),
+        }
+    }
+
+    fn read(&mut self) -> Option<u8> {
+        let byte = self.consumer.pop()?;
+
+        // A gap before this byte can not be reported anymore
+        self.gaps.retain(|gap| gap.position > 0);
+        for gap in &mut self.gaps {
+            gap.position -= 1;
+        }
+
+        Some(byte)
+    }
+
+    pub(crate) fn read_available(&mut self, buffer: &mut Vec<u8>) -> usize {
+        let start = buffer.len();
+        buffer.resize(start + self.consumer.len(), 0);
+
+        self.consumer.pop_slice(&mut buffer[start..])
+    }
+
+    pub(crate) fn take_gaps(&mut self) -> Vec<Gap> {
+        std::mem::take(&mut self.gaps)
+    }
+}
+
+pub struct USBPort {
+    serialport: Box<dyn SerialPort>,
+    buffer: OverflowBuffer,
 }
 
 impl USBPort {
-    fn new<P: AsRef<Path>>(dev_path: P) -> Self {
+    fn new<P: AsRef<Path>>(dev_path: P, serial: SerialSettings, buffer: BufferOptions) -> Self {
         let os_path = dev_path.as_ref().to_str().unwrap();
 
-        let port = serialport::new(os_path, 115_200)
-            .parity(Parity::None)
-            .stop_bits(StopBits::One)
-            .data_bits(DataBits::Eight)
+        let port = serialport::new(os_path, serial.baud_rate)
+            .parity(serial.format.parity)
+            .stop_bits(serial.format.stop_bits)
+            .data_bits(serial.format.data_bits)
+            .flow_control(serial.flow_control)
             // 1 start bit
             .open()
             .expect("Port does not exist"); // TODO error forwarding
 
-        let ringbuffer = RingBuffer::new(4096);
-        let (producer, consumer) = ringbuffer.split();
-
         Self {
             serialport: port,
-            producer,
-            consumer,
+            buffer: OverflowBuffer::new(buffer),
         }
     }
 }
@@ -78,48 +401,62 @@ impl Port for USBPort {
     fn fetch(&mut self) {
         let mut buffer = [0; 1024];
 
-        // Read data and add to buffer
-        let size = match self.serialport.read(buffer.as_mut()) {
-            Ok(size) => size,
-            Err(e) => {
-                // Timeing out is regular behavior
-                if e.kind() != TimedOut {
-                    println!("ERROR: Failed to read from serial port {:?}", e);
-                }
-                return
-            },
-        };
+        // Read data and add to buffer, until the serial port has no more
+        loop {
+            let free = match self.buffer.policy {
+                OverflowPolicy::Block => buffer.len().min(self.buffer.remaining()),
+                _ => buffer.len(),
+            };
+            if free == 0 {
+                return;
+            }
 
-        if size > 0 {
-            self.producer.write(&buffer[..size]).unwrap();
+            let size = match self.serialport.read(&mut buffer[..free]) {
+                Ok(size) => size,
+                Err(e) => {
+                    // Timeing out is regular behavior
+                    if e.kind() != TimedOut {
+                        println!("ERROR: Failed to read from serial port {:?}", e);
+                    }
+                    return
+                },
+            };
+
+            self.buffer.push(&buffer[..size]);
+
+            if size < free {
+                return;
+            }
         }
     }
 
     fn read(&mut self) -> Option<u8> {
-        if self.consumer.is_empty() {
-            return None
-        } else {
-            let mut buf: [u8; 1] = [0];
+        self.buffer.read()
+    }
 
-            self.consumer.read_exact(buf.as_mut()).unwrap();
+    fn read_available(&mut self, buffer: &mut Vec<u8>) -> usize {
+        self.buffer.read_available(buffer)
+    }
 
-            Some(buf[0])
-        }
+    fn take_gaps(&mut self) -> Vec<Gap> {
+        self.buffer.take_gaps()
     }
 }
 
-/// Port with a byte array as input. Useful for testing without actual serial port.
+/// Port with a file as input. Useful for replaying captured telegrams.
 pub struct FilePort {
     reader: BufReader<File>,
+    end_of_file: bool,
 }
 
 impl FilePort {
     fn new<P: AsRef<Path>>(path: P) -> Result<Self, std::io::Error> {
         let file = File::open(path)?;
-        let mut reader = BufReader::new(file);
+        let reader = BufReader::new(file);
 
         Ok(Self {
             reader,
+            end_of_file: false,
         })
     }
 }
@@ -129,10 +466,132 @@ impl Port for FilePort {
 
     fn read(&mut self) -> Option<u8> {
         let mut buf: [u8; 1] = [0];
-        if let Ok(_) = self.reader.read_exact(buf.as_mut()) {
+        if self.reader.read_exact(buf.as_mut()).is_ok() {
             Some(buf[0])
         } else {
+            self.end_of_file = true;
             None
         }
     }
+
+    /// Read the file in chunks, so large captures are not loaded in memory at once.
+    fn read_available(&mut self, buffer: &mut Vec<u8>) -> usize {
+        let mut chunk = [0; 4096];
+        let size = self.reader.read(&mut chunk).unwrap_or(0);
+        buffer.extend_from_slice(&chunk[..size]);
+        self.end_of_file = size == 0;
+
+        size
+    }
+
+    fn is_closed(&self) -> bool {
+        self.end_of_file
+    }
+}
+
+/// Port with a byte array as input. Useful for testing without actual serial port.
+#[cfg(test)]
+pub struct DataPort {
+    data: Vec<u8>,
+    position: usize,
+}
+
+#[cfg(test)]
+impl DataPort {
+    fn new(data: &[u8]) -> Self {
+        Self {
+            data: data.to_vec(),
+            position: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+impl Port for DataPort {
+    fn fetch(&mut self) {}
+
+    fn read(&mut self) -> Option<u8> {
+        let byte = self.data.get(self.position).copied();
+        if byte.is_some() {
+            self.position += 1;
+        }
+
+        byte
+    }
+
+    fn is_closed(&self) -> bool {
+        self.position == self.data.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::port::{BufferOptions, Gap, OverflowBuffer, OverflowPolicy, SerialFormat, SerialSettings};
+
+    fn buffer(overflow: OverflowPolicy) -> OverflowBuffer {
+        OverflowBuffer::new(BufferOptions { size: 8, overflow })
+    }
+
+    fn read_all(buffer: &mut OverflowBuffer) -> Vec<u8> {
+        let mut data = Vec::new();
+        buffer.read_available(&mut data);
+
+        data
+    }
+
+    #[test]
+    fn drop_newest() {
+        let mut buffer = buffer(OverflowPolicy::DropNewest);
+        buffer.push(b"123456");
+        buffer.push(b"789");
+        buffer.push(b"0");
+
+        assert_eq!(read_all(&mut buffer), b"12345678");
+        assert_eq!(buffer.take_gaps(), vec![Gap { position: 8, size: 2 }]);
+        assert_eq!(buffer.take_gaps(), vec![]);
+    }
+
+    #[test]
+    fn drop_oldest() {
+        let mut buffer = buffer(OverflowPolicy::DropOldest);
+        buffer.push(b"123456");
+        buffer.push(b"789");
+        buffer.push(b"0");
+
+        assert_eq!(read_all(&mut buffer), b"34567890");
+        assert_eq!(buffer.take_gaps(), vec![Gap { position: 0, size: 2 }]);
+
+        buffer.push(b"abcdefghij");
+        assert_eq!(read_all(&mut buffer), b"cdefghij");
+        assert_eq!(buffer.take_gaps(), vec![Gap { position: 0, size: 2 }]);
+    }
+
+    #[test]
+    fn single_reads_move_gaps() {
+        let mut buffer = buffer(OverflowPolicy::DropNewest);
+        buffer.push(b"123456789");
+        buffer.read();
+
+        assert_eq!(buffer.take_gaps(), vec![Gap { position: 7, size: 1 }]);
+    }
+
+    #[test]
+    #[cfg(feature = "async")]
+    fn extend_keeps_gaps_of_data() {
+        let mut buffer = buffer(OverflowPolicy::DropOldest);
+        buffer.push(b"123");
+        buffer.extend(b"abcdef", &[Gap { position: 2, size: 4 }]);
+
+        assert_eq!(read_all(&mut buffer), b"23abcdef");
+        assert_eq!(buffer.take_gaps(), vec![Gap { position: 4, size: 4 }, Gap { position: 0, size: 1 }]);
+    }
+
+    #[test]
+    fn serial_format() {
+        assert_eq!("7E1".parse::<SerialFormat>(), Ok(SerialFormat::DSMR2));
+        assert_eq!("8n1".parse::<SerialFormat>(), Ok(SerialFormat::DSMR4));
+        assert!("8X1".parse::<SerialFormat>().is_err());
+        assert!("8N".parse::<SerialFormat>().is_err());
+        assert_eq!(SerialSettings::DSMR2.to_string(), "9600 baud 7E1");
+    }
 }
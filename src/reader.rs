@@ -1,80 +1,219 @@
-use nom::AsBytes;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
 use crate::data_frame::RawFrame;
-use crate::port::Port;
+use crate::port::{Gap, Port};
 
-enum ReaderState {
-    LookingForHeader,
-    LookingForFooter,
-    LookingForEnd,
+/// Counters of the data read by a `FrameReader`. Discarded data usually means a bad connection
+/// with the meter.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct ReaderStatistics {
+    /// Complete frames that were read
+    pub frames: u64,
+    /// Frames that were cut off, too large, stalled or not valid text
+    pub discarded_frames: u64,
+    /// Bytes outside of frames, and bytes of the discarded frames
+    pub discarded_bytes: u64,
+    /// Times the port lost data, because its buffer overflowed
+    pub overflows: u64,
+    /// Bytes lost by the port
+    pub lost_bytes: u64,
 }
 
-pub struct FrameReader {
-    state: ReaderState,
+/// Splits the output of a port into frames, from a '/' up to the end of the line with the '!'.
+///
+/// A '/' before the end of a frame is the header of a new frame, which means the previous one
+/// was cut off. Frames in which the port lost data, that grow beyond the maximum size or that
+/// do not complete within the timeout are discarded.
+pub(crate) struct FrameSplitter {
     buffer: Vec<u8>,
-    port: Box<dyn Port>,
+    pub(crate) max_frame_size: usize,
+    pub(crate) timeout: Duration,
+    /// When the incomplete frame at the start of the buffer started to come in
+    frame_started: Option<Instant>,
+    /// Positions in the buffer where the port lost data
+    gaps: Vec<usize>,
+    pub(crate) statistics: ReaderStatistics,
 }
 
-impl FrameReader {
-    pub fn new(port: Box<dyn Port>) -> Self {
+impl FrameSplitter {
+    pub(crate) fn new() -> Self {
         Self {
-            state: ReaderState::LookingForHeader,
-            buffer: Default::default(),
-            port,
+            buffer: Vec::new(),
+            max_frame_size: 8192,
+            timeout: Duration::from_secs(5),
+            frame_started: None,
+            gaps: Vec::new(),
+            statistics: Default::default(),
         }
     }
 
-    /// Read the next byte from the port, and once a whole frame is available,
-    /// return it.
-    pub fn read_next_byte(&mut self) -> Option<RawFrame> {
-        self.port.fetch();
+    /// Add data read from a port, with the gaps the port reported in it.
+    pub(crate) fn extend(&mut self, data: &[u8], gaps: Vec<Gap>) {
+        for gap in gaps {
+            self.gaps.push(self.buffer.len() + gap.position);
+            self.statistics.overflows += 1;
+            self.statistics.lost_bytes += gap.size as u64;
+        }
+        self.buffer.extend_from_slice(data);
+    }
 
-        if let Some(c) = self.port.read() {
-            match self.state {
-                ReaderState::LookingForHeader => {
-                    if c == b'/' {
-                        self.state = ReaderState::LookingForFooter;
-                        self.buffer.push(c);
-                    }
-                }
-                ReaderState::LookingForFooter => {
-                    self.buffer.push(c);
+    /// Take all complete frames out of the buffer. An incomplete frame at the end is kept until
+    /// the rest of it is added.
+    pub(crate) fn split(&mut self) -> Vec<RawFrame> {
+        let mut frames = Vec::new();
+        let mut position = 0;
 
-                    if c == b'!' {
-                        self.state = ReaderState::LookingForEnd
-                    }
+        loop {
+            let data = &self.buffer[position..];
+
+            let start = match data.iter().position(|c| *c == b'/') {
+                Some(start) => start,
+                None => {
+                    self.statistics.discarded_bytes += data.len() as u64;
+                    position = self.buffer.len();
+                    break;
                 }
-                ReaderState::LookingForEnd => {
-                    self.buffer.push(c);
-
-                    if c == b'\n' {
-                        let frame_data = match std::str::from_utf8(self.buffer.as_bytes()) {
-                            Ok(frame_data) => Some(frame_data.to_string()),
-                            Err(_) => None,
-                        };
-
-                        self.state = ReaderState::LookingForHeader;
-                        self.buffer = Default::default();
-
-                        return if let Some(frame_content) = frame_data {
-                            Some(RawFrame::new(frame_content))
-                        } else {
-                            // Skip over invalid frame
-                            None
+            };
+            self.statistics.discarded_bytes += start as u64;
+            position += start;
+
+            let data = &self.buffer[position..];
+            let end = data.iter()
+                .position(|c| *c == b'!')
+                .and_then(|footer| data[footer..].iter().position(|c| *c == b'\n').map(|end| footer + end + 1));
+
+            // Continue at the next header after lost data, instead of joining the halves of
+            // two frames
+            let frame_end = end.map_or(usize::MAX, |end| position + end);
+            if let Some(gap) = self.gaps.iter().copied().find(|gap| *gap > position && *gap < frame_end) {
+                self.discard_frame(gap - position);
+                position = gap;
+                continue;
+            }
+
+            // A new header before the end means the frame was cut off
+            if let Some(restart) = data[1..end.unwrap_or(data.len())].iter().position(|c| *c == b'/') {
+                self.discard_frame(restart + 1);
+                position += restart + 1;
+                continue;
+            }
+
+            match end {
+                Some(end) if end <= self.max_frame_size => {
+                    // Invalid frames are skipped over
+                    match std::str::from_utf8(&data[..end]) {
+                        Ok(frame_data) => {
+                            frames.push(RawFrame::new(frame_data.to_string()));
+                            self.statistics.frames += 1;
                         }
+                        Err(_) => self.discard_frame(end),
                     }
+                    position += end;
+                }
+                Some(end) => {
+                    self.discard_frame(end);
+                    position += end;
+                }
+                None if data.len() > self.max_frame_size => {
+                    self.discard_frame(data.len());
+                    position = self.buffer.len();
                 }
+                None => break,
             }
         }
 
-        None
+        self.buffer.drain(..position);
+        self.gaps.retain(|gap| *gap > position);
+        for gap in &mut self.gaps {
+            *gap -= position;
+        }
+        self.check_timeout(position > 0);
+
+        frames
+    }
+
+    /// Discard the incomplete frame in the buffer if it stalled. `new_frame` tells whether
+    /// the buffer starts with a different frame than before.
+    fn check_timeout(&mut self, new_frame: bool) {
+        if self.buffer.is_empty() || new_frame {
+            self.frame_started = None;
+        }
+        if self.buffer.is_empty() {
+            return;
+        }
+
+        let started = *self.frame_started.get_or_insert_with(Instant::now);
+        if started.elapsed() > self.timeout {
+            self.discard_frame(self.buffer.len());
+            self.buffer.clear();
+            self.frame_started = None;
+        }
+    }
+
+    fn discard_frame(&mut self, size: usize) {
+        self.statistics.discarded_frames += 1;
+        self.statistics.discarded_bytes += size as u64;
+    }
+}
+
+/// Reads frames from a port. See `FrameSplitter` for how the data is split into frames.
+pub struct FrameReader {
+    splitter: FrameSplitter,
+    /// Frames that were read, but not returned yet by `read_next_frame`
+    frames: VecDeque<RawFrame>,
+    port: Box<dyn Port>,
+}
+
+impl FrameReader {
+    pub fn new(port: Box<dyn Port>) -> Self {
+        Self {
+            splitter: FrameSplitter::new(),
+            frames: Default::default(),
+            port,
+        }
+    }
+
+    /// Set the size in bytes above which frames are discarded. This is 8192 by default, where
+    /// telegrams are usually below 2000 bytes.
+    pub fn with_max_frame_size(mut self, max_frame_size: usize) -> Self {
+        self.splitter.max_frame_size = max_frame_size;
+        self
+    }
+
+    /// Set the time after which an incomplete frame is discarded. This is 5 seconds by default.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.splitter.timeout = timeout;
+        self
+    }
+
+    pub fn statistics(&self) -> ReaderStatistics {
+        self.splitter.statistics
+    }
+
+    /// Read everything that is available from the port, and return all complete frames in it.
+    /// An incomplete frame at the end is kept until the rest of it is read.
+    pub fn read_frames(&mut self) -> Vec<RawFrame> {
+        self.port.fetch();
+
+        let mut data = Vec::new();
+        self.port.read_available(&mut data);
+        self.splitter.extend(&data, self.port.take_gaps());
+
+        let mut frames: Vec<RawFrame> = self.frames.drain(..).collect();
+        frames.extend(self.splitter.split());
+
+        frames
     }
 
     /// Read next frame. Blocking when no data is available.
     fn read_next_frame(&mut self) -> RawFrame {
         loop {
-            if let Some(raw_frame) = self.read_next_byte() {
+            if let Some(raw_frame) = self.frames.pop_front() {
                 return raw_frame;
             }
+
+            let frames = self.read_frames();
+            self.frames.extend(frames);
         }
     }
 }
@@ -89,7 +228,12 @@ impl Iterator for FrameReader {
 
 #[cfg(test)]
 mod tests {
-    use crate::{FrameReader, PortBuilder};
+    use std::time::Duration;
+    use crate::port::PortBuilder;
+    use crate::reader::FrameReader;
+    use std::collections::VecDeque;
+    use crate::port::{Gap, Port};
+    use crate::reader::ReaderStatistics;
 
     fn reader_from_str(str: &'static str) -> FrameReader {
         FrameReader::new(PortBuilder::from_data(str.as_bytes()))
@@ -112,6 +256,15 @@ mod tests {
         assert_eq!(frame.len(), 69);
     }
 
+    #[test]
+    fn all_available_frames() {
+        let mut reader = reader_from_str("/ISK5\\2M550E-1012\r\n\r\n1-3:0.2.8(50)\r\n0-0:1.0.0(211227133446W)\r\n!38AF\r\n/ISK5\\2M550E-1012\r\n\r\n1-3:0.2.8(50)\r\n0-0:1.0.0(211227133446W)\r\n!38AF\r\n/ISK5\\2M550E-1012\r\n\r\n1-3");
+
+        assert_eq!(reader.read_frames().len(), 2);
+        assert_eq!(reader.splitter.buffer, b"/ISK5\\2M550E-1012\r\n\r\n1-3");
+        assert_eq!(reader.read_frames().len(), 0);
+    }
+
     #[test]
     fn start_on_broken_frame() {
         let mut reader = reader_from_str("brokenframe/ISK5\\2M550E-1012\r\n\r\n1-3:0.2.8(50)\r\n0-0:1.0.0(211227133446W)\r\n!38AF\r\n"); // 69
@@ -119,4 +272,84 @@ mod tests {
         let frame = reader.read_next_frame();
         assert_eq!(frame.len(), 69);
     }
+
+    #[test]
+    fn restart_on_new_header() {
+        let mut reader = reader_from_str("/ISK5\\2M550E-1012\r\n\r\n1-3:0.2.8(50)\r\n/ISK5\\2M550E-1012\r\n\r\n1-3:0.2.8(50)\r\n0-0:1.0.0(211227133446W)\r\n!38AF\r\n");
+
+        let frames = reader.read_frames();
+
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].len(), 69);
+        assert_eq!(reader.statistics(), ReaderStatistics { frames: 1, discarded_frames: 1, discarded_bytes: 36, ..Default::default() });
+    }
+
+    /// Port that returns a chunk of data on every read, with the given gaps.
+    struct ChunkPort {
+        chunks: VecDeque<(&'static str, Vec<Gap>)>,
+        gaps: Vec<Gap>,
+    }
+
+    impl Port for ChunkPort {
+        fn fetch(&mut self) {}
+
+        fn read(&mut self) -> Option<u8> {
+            None
+        }
+
+        fn read_available(&mut self, buffer: &mut Vec<u8>) -> usize {
+            let (chunk, gaps) = self.chunks.pop_front().unwrap_or_default();
+            buffer.extend_from_slice(chunk.as_bytes());
+            self.gaps = gaps;
+
+            chunk.len()
+        }
+
+        fn take_gaps(&mut self) -> Vec<Gap> {
+            std::mem::take(&mut self.gaps)
+        }
+    }
+
+    #[test]
+    fn resync_after_lost_data() {
+        let port = ChunkPort {
+            chunks: VecDeque::from([
+                ("/ISK5\\2M550E-1012\r\n\r\n1-3:0.2.8(50)\r\n0-0:1.0.0(2112", vec![]),
+                // The end of the first frame and the start of the second one were lost
+                ("1-0:1.8.1(001382.570*kWh)\r\n!38AF\r\n/ISK5\\2M550E-1012\r\n\r\n1-3:0.2.8(50)\r\n0-0:1.0.0(211227133446W)\r\n!38AF\r\n", vec![Gap { position: 0, size: 100 }]),
+            ]),
+            gaps: vec![],
+        };
+        let mut reader = FrameReader::new(Box::new(port));
+
+        assert_eq!(reader.read_frames().len(), 0);
+        let frames = reader.read_frames();
+
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].len(), 69);
+        assert_eq!(reader.statistics(), ReaderStatistics { frames: 1, discarded_frames: 1, discarded_bytes: 84, overflows: 1, lost_bytes: 100 });
+    }
+
+    #[test]
+    fn frame_too_large() {
+        let mut reader = reader_from_str("/ISK5\\2M550E-1012\r\n\r\n1-3:0.2.8(50)\r\n1-3:0.2.8(50)\r\n1-3:0.2.8(50)\r\n1-3:0.2.8(50)\r\n")
+            .with_max_frame_size(64);
+
+        assert_eq!(reader.read_frames().len(), 0);
+        assert!(reader.splitter.buffer.is_empty());
+        assert_eq!(reader.statistics(), ReaderStatistics { frames: 0, discarded_frames: 1, discarded_bytes: 81, ..Default::default() });
+    }
+
+    #[test]
+    fn stalled_frame() {
+        let mut reader = reader_from_str("garbage/ISK5\\2M550E-1012\r\n\r\n1-3")
+            .with_timeout(Duration::ZERO);
+
+        reader.read_frames();
+        std::thread::sleep(Duration::from_millis(1));
+        reader.read_frames();
+
+        assert!(reader.splitter.buffer.is_empty());
+        assert_eq!(reader.statistics(), ReaderStatistics { frames: 0, discarded_frames: 1, discarded_bytes: 31, ..Default::default() });
+    }
 }
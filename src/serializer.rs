@@ -0,0 +1,286 @@
+use chrono::{DateTime, FixedOffset};
+use chrono_tz::{OffsetComponents, Tz};
+use crate::crc::crc16;
+use crate::data_frame::{CustomValue, DataFrame, Measurement, MonthlyDemandPeak, Object, Phase, TelegramObject};
+use crate::decimal::Decimal;
+use crate::obis::ObisCode;
+use crate::parser::Protocol;
+
+/// Writes objects as P1 telegrams, the reverse of `FrameParser`.
+///
+/// Numbers are padded to the number of digits DSMR 5 defines for the object, so telegrams of
+/// meters that follow the specification are written byte for byte as they were received.
+/// Unknown objects are written exactly as they were sent.
+pub struct TelegramWriter {
+    protocol: Protocol,
+    time_zone: Tz,
+}
+
+impl Default for TelegramWriter {
+    fn default() -> Self {
+        Self::new(Protocol::default())
+    }
+}
+
+impl TelegramWriter {
+    pub fn new(protocol: Protocol) -> Self {
+        Self {
+            protocol,
+            time_zone: chrono_tz::Europe::Amsterdam,
+        }
+    }
+
+    /// Set the time zone of the meter, which decides the DST flag of the timestamps.
+    /// This is Europe/Amsterdam by default.
+    pub fn with_time_zone(mut self, time_zone: Tz) -> Self {
+        self.time_zone = time_zone;
+        self
+    }
+
+    /// Write the header and all objects of a data frame, with a new checksum.
+    pub fn write_frame(&self, data_frame: &DataFrame) -> String {
+        self.telegram(data_frame.prefix(), data_frame.baud_rate(), data_frame.identifier(), data_frame.objects())
+    }
+
+    /// Write a telegram with header "/{prefix}5{identifier}".
+    pub fn write(&self, prefix: &str, identifier: &str, objects: &[TelegramObject]) -> String {
+        self.telegram(prefix, '5', identifier, objects)
+    }
+
+    fn telegram(&self, prefix: &str, baud_rate: char, identifier: &str, objects: &[TelegramObject]) -> String {
+        let mut telegram = format!("/{}{}{}\r\n\r\n", prefix, baud_rate, identifier);
+
+        for TelegramObject { obis, object } in objects {
+            telegram += &format!("{}{}\r\n", obis, self.value(*obis, object));
+        }
+
+        telegram.push('!');
+        if self.protocol == Protocol::Dsmr4 {
+            telegram += &format!("{:04X}", crc16(telegram.as_bytes()));
+        }
+        telegram += "\r\n";
+
+        telegram
+    }
+
+    /// Write a telegram of objects without an OBIS reference, using the reference DSMR 5
+    /// defines for each object. M-Bus readings are written as 0-n:24.2.1.
+    pub fn write_objects(&self, prefix: &str, identifier: &str, objects: &[Object]) -> String {
+        let objects: Vec<TelegramObject> = objects
+            .iter()
+            .map(|object| TelegramObject::new(obis_of(object), object.clone()))
+            .collect();
+
+        self.write(prefix, identifier, &objects)
+    }
+
+    /// Format the value of an object, including its parentheses.
+    fn value(&self, obis: ObisCode, object: &Object) -> String {
+        let tst = |time: &DateTime<FixedOffset>| format!("({})", self.timestamp(time, true));
+
+        match object {
+            Object::Version(v) => format!("({})", v),
+            Object::Time(t) => tst(t),
+            Object::EquipmentIdentifier(v) | Object::TextMessage(v) | Object::MBusEquipmentIdentifier(_, v) => hex(v),
+            Object::ElectricityDeliveredT1(v) | Object::ElectricityDeliveredT2(v)
+            | Object::ElectricityReceivedT1(v) | Object::ElectricityReceivedT2(v) => measurement(v, 9),
+            Object::TariffIndicator(v) => format!("({:04})", v),
+            Object::ElectricityDelivering(v) | Object::ElectricityReceiving(v)
+            | Object::PhaseDelivering(_, v) | Object::PhaseReceiving(_, v) | Object::AverageDemand(v) => measurement(v, 5),
+            Object::Voltage(_, v) => measurement(v, 4),
+            Object::Current(_, v) => measurement(v, 3),
+            Object::VoltageSags(_, v) | Object::VoltageSwells(_, v)
+            | Object::PowerFailures(v) | Object::LongPowerFailures(v) | Object::EmucsVersion(v) => format!("({:05})", v),
+            Object::PowerFailureLog(events) => {
+                let entries: String = events
+                    .iter()
+                    .map(|e| format!("{}({:010}*s)", tst(&e.end), e.duration.as_secs()))
+                    .collect();
+
+                format!("({})(0-0:96.7.19){}", events.len(), entries)
+            },
+            Object::MaximumDemand(peak) => format!("{}{}", tst(&peak.time), measurement(&peak.value, 5)),
+            Object::MaximumDemandHistory(peaks) => {
+                let entries: String = peaks.iter().map(|p| self.monthly_peak(p)).collect();
+
+                format!("({})(1-0:1.6.0)(1-0:1.6.0){}", peaks.len(), entries)
+            },
+            Object::MBusDeviceType(_, v) => format!("({:03})", v.code()),
+            // DSMR 2.2 and 3.0 put the value on the next line
+            Object::MBusReading(channel, r) if obis.d == 3 => format!(
+                "({})(00)(60)(1)(0-{}:24.2.1)({})\r\n({})",
+                self.timestamp(&r.time, false), channel, r.unit, number(r.value, 8)
+            ),
+            Object::MBusReading(_, r) => format!("{}{}", tst(&r.time), measurement(&Measurement::new(r.value, r.unit), 8)),
+            Object::Custom(_, v) => match v {
+                CustomValue::Integer(v) => format!("({})", v),
+                CustomValue::Measurement(v) => measurement(v, 0),
+                CustomValue::Text(v) => hex(v),
+                CustomValue::Time(t) => tst(t),
+            },
+            Object::Unknown(_, value) => value.clone(),
+        }
+    }
+
+    fn monthly_peak(&self, peak: &MonthlyDemandPeak) -> String {
+        // e-MUCS meters send this for months in which they were not active yet
+        let tst = |time: &Option<DateTime<FixedOffset>>| match time {
+            Some(time) => format!("({})", self.timestamp(time, true)),
+            None => String::from("(632525252525W)"),
+        };
+
+        format!("{}{}{}", tst(&peak.month), tst(&peak.time), measurement(&peak.value, 5))
+    }
+
+    /// Format a time as YYMMDDhhmmss in the time zone of the meter, with the DST flag if `dst`.
+    fn timestamp(&self, time: &DateTime<FixedOffset>, dst: bool) -> String {
+        let local = time.with_timezone(&self.time_zone);
+        let flag = match (dst, local.offset().dst_offset().is_zero()) {
+            (false, _) => "",
+            (true, true) => "W",
+            (true, false) => "S",
+        };
+
+        format!("{}{}", local.format("%y%m%d%H%M%S"), flag)
+    }
+}
+
+/// Format a number with its integer part padded to `digits` digits in total, e.g. 1382.570
+/// with 9 digits as 001382.570.
+fn number(value: Decimal, digits: u32) -> String {
+    let width = digits.max(value.scale() + 1) as usize + usize::from(value.scale() > 0);
+
+    format!("{:0>width$}", value.to_string(), width = width)
+}
+
+fn measurement(value: &Measurement, digits: u32) -> String {
+    format!("({}*{})", number(value.value, digits), value.unit)
+}
+
+fn hex(text: &str) -> String {
+    let hex: String = text.bytes().map(|b| format!("{:02X}", b)).collect();
+
+    format!("({})", hex)
+}
+
+/// OBIS reference DSMR 5 defines for an object.
+fn obis_of(object: &Object) -> ObisCode {
+    let phase = |phase: &Phase, l1: u8| match phase {
+        Phase::L1 => l1,
+        Phase::L2 => l1 + 20,
+        Phase::L3 => l1 + 40,
+    };
+
+    match object {
+        Object::Version(_) => ObisCode::new(1, 3, 0, 2, 8),
+        Object::Time(_) => ObisCode::new(0, 0, 1, 0, 0),
+        Object::EquipmentIdentifier(_) => ObisCode::new(0, 0, 96, 1, 1),
+        Object::TextMessage(_) => ObisCode::new(0, 0, 96, 13, 0),
+        Object::ElectricityDeliveredT1(_) => ObisCode::new(1, 0, 1, 8, 1),
+        Object::ElectricityDeliveredT2(_) => ObisCode::new(1, 0, 1, 8, 2),
+        Object::ElectricityReceivedT1(_) => ObisCode::new(1, 0, 2, 8, 1),
+        Object::ElectricityReceivedT2(_) => ObisCode::new(1, 0, 2, 8, 2),
+        Object::TariffIndicator(_) => ObisCode::new(0, 0, 96, 14, 0),
+        Object::ElectricityDelivering(_) => ObisCode::new(1, 0, 1, 7, 0),
+        Object::ElectricityReceiving(_) => ObisCode::new(1, 0, 2, 7, 0),
+        Object::Voltage(p, _) => ObisCode::new(1, 0, phase(p, 32), 7, 0),
+        Object::Current(p, _) => ObisCode::new(1, 0, phase(p, 31), 7, 0),
+        Object::PhaseDelivering(p, _) => ObisCode::new(1, 0, phase(p, 21), 7, 0),
+        Object::PhaseReceiving(p, _) => ObisCode::new(1, 0, phase(p, 22), 7, 0),
+        Object::VoltageSags(p, _) => ObisCode::new(1, 0, phase(p, 32), 32, 0),
+        Object::VoltageSwells(p, _) => ObisCode::new(1, 0, phase(p, 32), 36, 0),
+        Object::PowerFailures(_) => ObisCode::new(0, 0, 96, 7, 21),
+        Object::LongPowerFailures(_) => ObisCode::new(0, 0, 96, 7, 9),
+        Object::PowerFailureLog(_) => ObisCode::new(1, 0, 99, 97, 0),
+        Object::EmucsVersion(_) => ObisCode::new(0, 0, 96, 1, 4),
+        Object::AverageDemand(_) => ObisCode::new(1, 0, 1, 4, 0),
+        Object::MaximumDemand(_) => ObisCode::new(1, 0, 1, 6, 0),
+        Object::MaximumDemandHistory(_) => ObisCode::new(0, 0, 98, 1, 0),
+        Object::MBusDeviceType(c, _) => ObisCode::new(0, *c, 24, 1, 0),
+        Object::MBusEquipmentIdentifier(c, _) => ObisCode::new(0, *c, 96, 1, 0),
+        Object::MBusReading(c, _) => ObisCode::new(0, *c, 24, 2, 1),
+        Object::Custom(obis, _) | Object::Unknown(obis, _) => *obis,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::data_frame::{Measurement, Object, RawFrame, TelegramObject, Unit};
+    use crate::decimal::Decimal;
+    use crate::obis::ObisCode;
+    use crate::parser::{FrameParser, Protocol};
+    use crate::serializer::TelegramWriter;
+
+    const TELEGRAM: &str = "/ISK5\\2M550E-1012\r\n\r\n1-3:0.2.8(50)\r\n0-0:1.0.0(211227133446W)\r\n0-0:96.1.1(4530303439303037343733383433363139)\r\n1-0:1.8.1(001382.570*kWh)\r\n1-0:1.8.2(001749.559*kWh)\r\n1-0:2.8.1(000000.000*kWh)\r\n1-0:2.8.2(000000.000*kWh)\r\n0-0:96.14.0(0002)\r\n1-0:1.7.0(00.200*kW)\r\n1-0:2.7.0(00.000*kW)\r\n0-0:96.7.21(00008)\r\n0-0:96.7.9(00003)\r\n1-0:99.97.0(2)(0-0:96.7.19)(190904052824S)(0000000293*s)(201115085142W)(0000006033*s)\r\n1-0:32.32.0(00006)\r\n1-0:32.36.0(00001)\r\n0-0:96.13.0()\r\n1-0:32.7.0(230.5*V)\r\n1-0:31.7.0(001*A)\r\n1-0:21.7.0(00.164*kW)\r\n1-0:22.7.0(00.000*kW)\r\n0-1:24.1.0(003)\r\n0-1:96.1.0(4730303634303032303039363134343230)\r\n0-1:24.2.1(211227133003W)(00409.167*m3)\r\n!38AF\r\n";
+    const EMUCS_TELEGRAM: &str = "/FLU5\\253769484_A\r\n\r\n0-0:96.1.4(50217)\r\n0-0:96.1.1(3153414733313031303231363035)\r\n0-0:1.0.0(200512135409S)\r\n1-0:1.8.1(000000.034*kWh)\r\n1-0:1.8.2(000015.758*kWh)\r\n1-0:2.8.1(000000.000*kWh)\r\n1-0:2.8.2(000000.011*kWh)\r\n1-0:1.4.0(02.351*kW)\r\n1-0:1.6.0(200509134558S)(02.589*kW)\r\n0-0:98.1.0(3)(1-0:1.6.0)(1-0:1.6.0)(200501000000S)(200423192538S)(03.695*kW)(200401000000S)(200305122139S)(05.980*kW)(200301000000S)(632525252525W)(00.000*kW)\r\n0-0:96.14.0(0001)\r\n1-0:1.7.0(00.000*kW)\r\n1-0:2.7.0(00.000*kW)\r\n0-0:96.13.0()\r\n0-1:24.1.0(003)\r\n0-1:96.1.1(37464C4F32313139303333373333)\r\n0-1:24.2.3(200512134558S)(00112.384*m3)\r\n!4B55\r\n";
+
+    fn parse(input: &str) -> crate::data_frame::DataFrame {
+        FrameParser::default().parse(RawFrame::new(input.to_string())).unwrap()
+    }
+
+    #[test]
+    fn round_trip() {
+        assert_eq!(TelegramWriter::default().write_frame(&parse(TELEGRAM)), TELEGRAM);
+    }
+
+    #[test]
+    fn emucs_round_trip() {
+        let data_frame = parse(EMUCS_TELEGRAM);
+
+        // This meter sends summer time flags in winter, which are written as they should be
+        let telegram = TelegramWriter::default().write_frame(&data_frame);
+
+        assert_eq!(parse(&telegram).objects(), data_frame.objects());
+    }
+
+    #[test]
+    fn anonymize_equipment_identifier() {
+        let data_frame = parse(TELEGRAM);
+        let objects: Vec<TelegramObject> = data_frame.objects()
+            .iter()
+            .cloned()
+            .map(|mut o| {
+                if let Object::EquipmentIdentifier(id) = &mut o.object {
+                    *id = String::from("E0000000000000000");
+                }
+                o
+            })
+            .collect();
+
+        let telegram = TelegramWriter::default().write(data_frame.prefix(), data_frame.identifier(), &objects);
+        let rewritten = parse(&telegram);
+
+        assert!(telegram.contains("0-0:96.1.1(4530303030303030303030303030303030)\r\n"));
+        assert_eq!(rewritten.meter_identity(), "E0000000000000000");
+        assert_eq!(rewritten.data.electricity_delivered_t1, data_frame.data.electricity_delivered_t1);
+    }
+
+    #[test]
+    fn objects_use_default_references() {
+        let objects = vec![
+            Object::Version(50),
+            Object::ElectricityDeliveredT1(Measurement::new(Decimal::new(1234, 3), Unit::KWh)),
+        ];
+
+        let telegram = TelegramWriter::default().write_objects("ISK", "\\2M550E-1012", &objects);
+
+        assert!(telegram.starts_with("/ISK5\\2M550E-1012\r\n\r\n1-3:0.2.8(50)\r\n1-0:1.8.1(000001.234*kWh)\r\n!"));
+        assert_eq!(parse(&telegram).object(ObisCode::new(1, 0, 1, 8, 1)), Some(&objects[1]));
+    }
+
+    #[test]
+    fn legacy_telegram_has_no_checksum() {
+        let telegram = TelegramWriter::new(Protocol::Dsmr2).write_objects("ISk", "\\2ME382-1003", &[Object::TariffIndicator(1)]);
+
+        assert_eq!(telegram, "/ISk5\\2ME382-1003\r\n\r\n0-0:96.14.0(0001)\r\n!\r\n");
+    }
+
+    #[test]
+    fn legacy_round_trip_keeps_baud_rate() {
+        let telegram = "/ISk3\\2ME382-1003\r\n\r\n0-0:96.14.0(0001)\r\n!\r\n";
+        let data_frame = FrameParser::new(Protocol::Dsmr2).parse(RawFrame::new(telegram.to_string())).unwrap();
+
+        assert_eq!(data_frame.baud_rate(), '3');
+        assert_eq!(TelegramWriter::new(Protocol::Dsmr2).write_frame(&data_frame), telegram);
+    }
+}
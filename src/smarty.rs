@@ -0,0 +1,320 @@
+use std::collections::VecDeque;
+use aes_gcm::{AesGcm, AeadInPlace, KeyInit, Nonce, Tag};
+use aes_gcm::aes::Aes128;
+use aes_gcm::aead::consts::U12;
+use crate::port::{Gap, Port};
+
+type Aes128Gcm12 = AesGcm<Aes128, U12, U12>;
+
+/// Start of a general-glo-ciphering DLMS frame.
+const FRAME_TAG: u8 = 0xDB;
+const SYSTEM_TITLE_LENGTH: usize = 8;
+/// Security control byte, frame counter
+const SECURITY_HEADER_LENGTH: usize = 1 + 4;
+const GCM_TAG_LENGTH: usize = 12;
+/// Frames are at most a few kilobytes, anything larger is garbage.
+const MAX_FRAME_LENGTH: usize = 8192;
+
+/// Authentication key used by all Smarty meters.
+pub const DEFAULT_AAD: [u8; 16] = [
+    0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77,
+    0x88, 0x99, 0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF,
+];
+
+/// Port that decrypts the P1 output of Luxembourg Smarty meters.
+///
+/// The meter wraps every telegram in a DLMS frame encrypted with AES-128-GCM:
+/// `DB 08 <system title> 82 <length> 30 <frame counter> <ciphertext> <tag>`. This port reads
+/// those frames from the inner port and outputs the decrypted telegrams.
+///
+/// A frame that the inner port lost bytes of can not be decrypted. It is dropped, and reported
+/// as a gap in the output with the size of the encrypted bytes that were lost.
+pub struct DecryptingPort {
+    port: Box<dyn Port>,
+    cipher: Aes128Gcm12,
+    aad: [u8; 16],
+    input: Vec<u8>,
+    output: VecDeque<u8>,
+    /// Positions are relative to the start of the output
+    gaps: Vec<Gap>,
+}
+
+impl DecryptingPort {
+    /// Create a port with the key of the meter, provided by the grid operator. When no AAD is
+    /// given, the default Smarty authentication key is used.
+    pub fn new(port: Box<dyn Port>, key: [u8; 16], aad: Option<[u8; 16]>) -> Self {
+        Self {
+            port,
+            cipher: Aes128Gcm12::new(&key.into()),
+            aad: aad.unwrap_or(DEFAULT_AAD),
+            input: Vec::new(),
+            output: VecDeque::new(),
+            gaps: Vec::new(),
+        }
+    }
+
+    /// Decrypt all complete frames in the input buffer.
+    fn process_input(&mut self) {
+        loop {
+            // Skip to the start of the next frame
+            match self.input.iter().position(|b| *b == FRAME_TAG) {
+                Some(start) => { self.input.drain(..start); },
+                None => {
+                    self.input.clear();
+                    return;
+                }
+            }
+
+            let (header_length, length) = match parse_frame_header(&self.input) {
+                FrameHeader::Incomplete => return,
+                FrameHeader::Invalid => {
+                    // Not an actual frame start, look for the next one
+                    self.input.remove(0);
+                    continue;
+                }
+                FrameHeader::Valid { header_length, length } => (header_length, length),
+            };
+
+            if self.input.len() < header_length + length {
+                return;
+            }
+
+            let frame: Vec<u8> = self.input.drain(..header_length + length).collect();
+            match self.decrypt(&frame[2..2 + SYSTEM_TITLE_LENGTH], &frame[header_length..]) {
+                Some(telegram) => self.output.extend(telegram),
+                None => println!("ERROR: Failed to decrypt frame, is the key correct?"),
+            }
+        }
+    }
+
+    /// Decrypt the payload of a frame, starting at the security control byte.
+    fn decrypt(&self, system_title: &[u8], payload: &[u8]) -> Option<Vec<u8>> {
+        let security_control = payload[0];
+        let frame_counter = &payload[1..SECURITY_HEADER_LENGTH];
+        let (ciphertext, tag) = payload[SECURITY_HEADER_LENGTH..].split_at(payload.len() - SECURITY_HEADER_LENGTH - GCM_TAG_LENGTH);
+
+        let mut iv = Vec::with_capacity(12);
+        iv.extend_from_slice(system_title);
+        iv.extend_from_slice(frame_counter);
+
+        let mut aad = Vec::with_capacity(17);
+        aad.push(security_control);
+        aad.extend_from_slice(&self.aad);
+
+        let mut buffer = ciphertext.to_vec();
+        self.cipher
+            .decrypt_in_place_detached(Nonce::from_slice(&iv), &aad, &mut buffer, Tag::from_slice(tag))
+            .ok()?;
+
+        Some(buffer)
+    }
+}
+
+enum FrameHeader {
+    Incomplete,
+    Invalid,
+    /// Length of the header, and the length of the rest of the frame
+    Valid { header_length: usize, length: usize },
+}
+
+/// Parse the header of a frame: tag, system title and a BER encoded length.
+fn parse_frame_header(data: &[u8]) -> FrameHeader {
+    let length_start = 2 + SYSTEM_TITLE_LENGTH;
+
+    if data.len() < length_start + 1 {
+        return FrameHeader::Incomplete;
+    }
+    if data[1] as usize != SYSTEM_TITLE_LENGTH {
+        return FrameHeader::Invalid;
+    }
+
+    let (length_bytes, length) = match data[length_start] {
+        length if length < 0x80 => (0, length as usize),
+        0x81 if data.len() > length_start + 1 => (1, data[length_start + 1] as usize),
+        0x82 if data.len() > length_start + 2 =>
+            (2, u16::from_be_bytes([data[length_start + 1], data[length_start + 2]]) as usize),
+        0x81 | 0x82 => return FrameHeader::Incomplete,
+        _ => return FrameHeader::Invalid,
+    };
+
+    if !(SECURITY_HEADER_LENGTH + GCM_TAG_LENGTH..=MAX_FRAME_LENGTH).contains(&length) {
+        return FrameHeader::Invalid;
+    }
+
+    FrameHeader::Valid {
+        header_length: length_start + 1 + length_bytes,
+        length,
+    }
+}
+
+impl Port for DecryptingPort {
+    fn fetch(&mut self) {
+        self.port.fetch();
+
+        let mut data = Vec::new();
+        self.port.read_available(&mut data);
+        let mut gaps = self.port.take_gaps();
+        gaps.sort_by_key(|gap| gap.position);
+
+        let mut start = 0;
+        for gap in gaps {
+            self.input.extend_from_slice(&data[start..gap.position]);
+            self.process_input();
+
+            // Only an incomplete frame is left, which is missing the lost bytes
+            let dropped = self.input.len();
+            self.input.clear();
+            self.gaps.push(Gap { position: self.output.len(), size: dropped + gap.size });
+
+            start = gap.position;
+        }
+
+        self.input.extend_from_slice(&data[start..]);
+        self.process_input();
+    }
+
+    fn read(&mut self) -> Option<u8> {
+        self.output.pop_front()
+    }
+
+    fn read_available(&mut self, buffer: &mut Vec<u8>) -> usize {
+        let size = self.output.len();
+        buffer.extend(self.output.drain(..));
+
+        size
+    }
+
+    fn take_gaps(&mut self) -> Vec<Gap> {
+        std::mem::take(&mut self.gaps)
+    }
+
+    fn is_closed(&self) -> bool {
+        self.port.is_closed() && self.output.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::data_frame::RawFrame;
+    use crate::parser::{decode_hex, FrameParser};
+    use crate::port::{Gap, Port, PortBuilder};
+    use crate::smarty::DecryptingPort;
+
+    const KEY: [u8; 16] = [
+        0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07,
+        0x08, 0x09, 0x0A, 0x0B, 0x0C, 0x0D, 0x0E, 0x0F,
+    ];
+
+    const FRAME: &str = "DB085341475905E4A2B7820081300000000AB2F3EA372A834F83A7333F0818CFBE92A8B7316B078F13AB2AFEBDB5C61778D5A22F1623CD6F6513CB79BBCC899D85A2FB3D1B9C4D24368683E0AC3A47898D2F9DB857C141881F64B3950877E8BDF371CBBD224FB9F489E70390EFCA8F124768763A378AFB79D1B790265999505D00A40467EF083F07CFF06822D69D";
+
+    const TELEGRAM: &str = "/Lux5\\SM3Xx\r\n\r\n1-3:0.2.8(42)\r\n0-0:1.0.0(211227133446W)\r\n1-0:1.8.0(000123.456*kWh)\r\n1-0:1.7.0(00.500*kW)\r\n!72C0\r\n";
+
+    /// Frame of a full Smarty telegram, encrypted with Python's `cryptography` package instead of
+    /// the `aes-gcm` crate. The IV is the system title and frame counter, and the AAD is the
+    /// security control byte and the default authentication key, as the Smarty P1 specification
+    /// defines them.
+    // TODO: replace with a capture of a real meter and its published key
+    const INDEPENDENT_KEY: [u8; 16] = [
+        0xAF, 0xB3, 0xF9, 0x3E, 0x3E, 0x72, 0x04, 0xED,
+        0xB3, 0xC2, 0x7F, 0x96, 0xDB, 0xD5, 0x1A, 0xE0,
+    ];
+    const INDEPENDENT_FRAME: &str = "DB08534147670000F1A28201EA3000013A7F24E2D8FD553CBB142F05C98F3FC07626BD675B3ED095DDEEE9BAE396946C13FAE801F5C83E3352CB8374DB34D22F02B9BC712D8F4727617F4EF4D66586244F001B4D375930C56B07DF03A09A8A699DCD811622844F9C9D0D328E3A942CDBA9F1BB76020CAC0698D6CC70103CB973355DC73F989240A24AC16DB78BDAC343E09153D9BDE59126E65E4F66C49CB5B7EBA25480D57511213F678BD7B1BB77686D46DA9029754C1825FBAE645A9F1DC6C4E10CD252FA6C0590C11F60BC650BCC590CB84F3FB98182796321EC0BB4A76B35A537F69461383DC219380E11DC696DC6A335239FDFD6ACFCBE9D90A08C242BF2AF5BE30BC1A21187CC4607A8EF8739E5ED1007A2F7B49428F612EA4D83EDB7E5099679FACE7F9C0C7C8191ADB2CB050BC64616D6E155C7188680EE0767CECC269C515540F231812BF37560CB5A0F62AC2221AD9940DE2D22AAF783EF942BB3615C9C9125F5857FD8CB8CBA165D340AA2B6357E6AF29E5E708F7B7BFA66D90417FC155B4CCDB5C77F610AA01DA870E6E5281FDFA6F3B18109F1CD2C540BF956E830ECB71252B638D8963AC7FA3CCEB15999E318084313295714A5C0E02D4A7E2BB23EFEE5E016EE84E67EA6977DE9F3ED133EBEE2BFDBB69C75DA7DA698D43BA41C62DB6974D409BFCC8EF75C89B9B046AB4F857578AC";
+
+    fn read_all(port: &mut DecryptingPort) -> Vec<u8> {
+        port.fetch();
+
+        let mut data = Vec::new();
+        while let Some(byte) = port.read() {
+            data.push(byte);
+        }
+
+        data
+    }
+
+    #[test]
+    fn decrypt_frame() {
+        let input = decode_hex(FRAME).unwrap();
+        let mut port = DecryptingPort::new(PortBuilder::from_data(&input), KEY, None);
+
+        assert_eq!(read_all(&mut port), TELEGRAM.as_bytes());
+    }
+
+    #[test]
+    fn decrypt_independently_encrypted_frame() {
+        let input = decode_hex(INDEPENDENT_FRAME).unwrap();
+        let mut port = DecryptingPort::new(PortBuilder::from_data(&input), INDEPENDENT_KEY, None);
+
+        let telegram = String::from_utf8(read_all(&mut port)).unwrap();
+        let data_frame = FrameParser::default().parse(RawFrame::new(telegram)).unwrap();
+
+        assert_eq!(data_frame.identifier(), "\\253833635_D");
+        assert_eq!(data_frame.data.l1.voltage, Some(232.1));
+    }
+
+    #[test]
+    fn skip_garbage_before_frame() {
+        let mut input = vec![0x00, 0xDB, 0x01, 0x42];
+        input.extend(decode_hex(FRAME).unwrap());
+        let mut port = DecryptingPort::new(PortBuilder::from_data(&input), KEY, None);
+
+        assert_eq!(read_all(&mut port), TELEGRAM.as_bytes());
+    }
+
+    #[test]
+    fn wrong_key() {
+        let input = decode_hex(FRAME).unwrap();
+        let mut port = DecryptingPort::new(PortBuilder::from_data(&input), [0; 16], None);
+
+        assert!(read_all(&mut port).is_empty());
+    }
+
+    #[test]
+    fn wrong_aad() {
+        let input = decode_hex(FRAME).unwrap();
+        let mut port = DecryptingPort::new(PortBuilder::from_data(&input), KEY, Some([0; 16]));
+
+        assert!(read_all(&mut port).is_empty());
+    }
+
+    /// Port that returns its data at once, with the given gaps.
+    struct GapPort {
+        data: Vec<u8>,
+        gaps: Vec<Gap>,
+    }
+
+    impl Port for GapPort {
+        fn fetch(&mut self) {}
+
+        fn read(&mut self) -> Option<u8> {
+            None
+        }
+
+        fn read_available(&mut self, buffer: &mut Vec<u8>) -> usize {
+            let size = self.data.len();
+            buffer.append(&mut self.data);
+
+            size
+        }
+
+        fn take_gaps(&mut self) -> Vec<Gap> {
+            std::mem::take(&mut self.gaps)
+        }
+    }
+
+    #[test]
+    fn drop_frame_with_lost_bytes() {
+        // The end of the first frame was lost
+        let frame = decode_hex(FRAME).unwrap();
+        let mut input = frame[..50].to_vec();
+        input.extend(&frame);
+        let port = GapPort { data: input, gaps: vec![Gap { position: 50, size: 10 }] };
+        let mut port = DecryptingPort::new(Box::new(port), KEY, None);
+
+        port.fetch();
+        let mut data = Vec::new();
+        port.read_available(&mut data);
+
+        assert_eq!(data, TELEGRAM.as_bytes());
+        assert_eq!(port.take_gaps(), vec![Gap { position: 0, size: 60 }]);
+    }
+}
@@ -0,0 +1,323 @@
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use futures_core::Stream;
+use tokio::io::{AsyncRead, ReadBuf};
+use tokio::sync::mpsc::{channel, Receiver};
+use tokio::sync::mpsc::error::TrySendError;
+use crate::data_frame::{DataFrame, RawFrame};
+use crate::parser::{FrameParser, ParseError};
+use crate::port::{BufferOptions, Gap, OverflowBuffer, OverflowPolicy, Port};
+use crate::reader::{FrameSplitter, ReaderStatistics};
+
+/// Time to wait before reading a blocking port again when it had no data.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Async version of `Port`.
+pub trait AsyncPort: Send + Unpin {
+    /// Append the bytes that are available to `buffer`, and return how many were added. Returns
+    /// 0 when the port is closed.
+    fn poll_read_available(&mut self, cx: &mut Context<'_>, buffer: &mut Vec<u8>) -> Poll<std::io::Result<usize>>;
+
+    /// Take where bytes were lost in the data returned by the last `poll_read_available`.
+    fn take_gaps(&mut self) -> Vec<Gap> {
+        Vec::new()
+    }
+}
+
+/// Any tokio reader is a port, e.g. a `TcpStream` to a P1 to network bridge.
+impl<R: AsyncRead + Send + Unpin> AsyncPort for R {
+    fn poll_read_available(&mut self, cx: &mut Context<'_>, buffer: &mut Vec<u8>) -> Poll<std::io::Result<usize>> {
+        let mut chunk = [0; 4096];
+        let mut read_buffer = ReadBuf::new(&mut chunk);
+
+        match Pin::new(self).poll_read(cx, &mut read_buffer) {
+            Poll::Ready(Ok(())) => {
+                buffer.extend_from_slice(read_buffer.filled());
+                Poll::Ready(Ok(read_buffer.filled().len()))
+            }
+            Poll::Ready(Err(e)) => Poll::Ready(Err(e)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// Runs a blocking `Port` on its own thread, so serial devices, files and decrypting ports can be
+/// read without blocking the runtime. The thread stops when the port is dropped, or when the
+/// port is closed and all its data was read.
+pub struct BlockingPort {
+    receiver: Receiver<(Vec<u8>, Vec<Gap>)>,
+    gaps: Vec<Gap>,
+}
+
+impl BlockingPort {
+    pub fn new(port: Box<dyn Port>) -> Self {
+        Self::with_buffer(port, BufferOptions::default())
+    }
+
+    /// Run a port with the given buffer options. Data is buffered on the thread while the
+    /// stream is not polled, and the overflow policy decides what happens when it is full.
+    /// With `OverflowPolicy::Block` the port is not read until the stream has taken the previous
+    /// data, so no data of files is lost.
+    pub fn with_buffer(mut port: Box<dyn Port>, options: BufferOptions) -> Self {
+        let (sender, receiver) = channel(1);
+
+        std::thread::spawn(move || {
+            let mut buffer = OverflowBuffer::new(options);
+
+            loop {
+                // Hand the buffered data over when the stream has taken the previous data
+                if !buffer.is_empty() {
+                    match sender.try_reserve() {
+                        Ok(permit) => {
+                            let mut data = Vec::new();
+                            buffer.read_available(&mut data);
+                            permit.send((data, buffer.take_gaps()));
+                        }
+                        Err(TrySendError::Full(())) => {}
+                        Err(TrySendError::Closed(())) => return,
+                    }
+                } else if port.is_closed() || sender.is_closed() {
+                    return;
+                }
+
+                port.fetch();
+
+                let mut data = Vec::new();
+                port.read_available(&mut data);
+                let gaps = port.take_gaps();
+
+                if data.is_empty() && gaps.is_empty() {
+                    // Wait for new data, or for the stream to take the buffered data
+                    if !port.is_closed() || !buffer.is_empty() {
+                        std::thread::sleep(POLL_INTERVAL);
+                    }
+                } else if options.overflow == OverflowPolicy::Block {
+                    // The port is not read until the stream has taken this data
+                    if sender.blocking_send((data, gaps)).is_err() {
+                        return;
+                    }
+                } else {
+                    buffer.extend(&data, &gaps);
+                }
+            }
+        });
+
+        Self {
+            receiver,
+            gaps: Vec::new(),
+        }
+    }
+}
+
+impl AsyncPort for BlockingPort {
+    fn poll_read_available(&mut self, cx: &mut Context<'_>, buffer: &mut Vec<u8>) -> Poll<std::io::Result<usize>> {
+        loop {
+            match self.receiver.poll_recv(cx) {
+                // Gaps without data are kept for the next data, as no data means the port closed
+                Poll::Ready(Some((data, gaps))) if data.is_empty() => self.gaps.extend(gaps),
+                Poll::Ready(Some((data, gaps))) => {
+                    buffer.extend_from_slice(&data);
+                    self.gaps.extend(gaps);
+                    return Poll::Ready(Ok(data.len()));
+                }
+                Poll::Ready(None) => return Poll::Ready(Ok(0)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+
+    fn take_gaps(&mut self) -> Vec<Gap> {
+        std::mem::take(&mut self.gaps)
+    }
+}
+
+/// Error of a frame in an `AsyncFrameReader`.
+#[derive(Debug)]
+pub enum ReadError {
+    /// Reading from the port failed
+    Io(std::io::Error),
+    /// A frame was read, but could not be parsed
+    Parse(ParseError),
+}
+
+impl std::fmt::Display for ReadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReadError::Io(e) => write!(f, "Failed to read from port: {}", e),
+            ReadError::Parse(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for ReadError {}
+
+/// Stream of the parsed frames of an async port. Frames are split like in `FrameReader`, and the
+/// stream ends when the port is closed.
+pub struct AsyncFrameReader<P> {
+    splitter: FrameSplitter,
+    /// Frames that were read, but not parsed yet
+    frames: VecDeque<RawFrame>,
+    port: P,
+    parser: FrameParser,
+}
+
+impl<P: AsyncPort> AsyncFrameReader<P> {
+    pub fn new(port: P, parser: FrameParser) -> Self {
+        Self {
+            splitter: FrameSplitter::new(),
+            frames: VecDeque::new(),
+            port,
+            parser,
+        }
+    }
+
+    /// Set the size in bytes above which frames are discarded. This is 8192 by default.
+    pub fn with_max_frame_size(mut self, max_frame_size: usize) -> Self {
+        self.splitter.max_frame_size = max_frame_size;
+        self
+    }
+
+    /// Set the time after which an incomplete frame is discarded. This is 5 seconds by default.
+    /// Stalled frames are only noticed when new data comes in.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.splitter.timeout = timeout;
+        self
+    }
+
+    pub fn statistics(&self) -> ReaderStatistics {
+        self.splitter.statistics
+    }
+}
+
+impl<P: AsyncPort> Stream for AsyncFrameReader<P> {
+    type Item = Result<DataFrame, ReadError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+
+        loop {
+            if let Some(raw_frame) = this.frames.pop_front() {
+                return Poll::Ready(Some(this.parser.parse(raw_frame).map_err(ReadError::Parse)));
+            }
+
+            let mut data = Vec::new();
+            match this.port.poll_read_available(cx, &mut data) {
+                Poll::Ready(Ok(0)) => return Poll::Ready(None),
+                Poll::Ready(Ok(_)) => {
+                    this.splitter.extend(&data, this.port.take_gaps());
+                    this.frames.extend(this.splitter.split());
+                }
+                Poll::Ready(Err(e)) => return Poll::Ready(Some(Err(ReadError::Io(e)))),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+    use futures::StreamExt;
+    use crate::parser::FrameParser;
+    use std::collections::VecDeque;
+    use crate::port::{BufferOptions, Gap, OverflowPolicy, Port, PortBuilder};
+    use crate::stream::{AsyncFrameReader, BlockingPort};
+
+    const TELEGRAMS: &[u8] = b"garbage/Lux5\\SM3Xx\r\n\r\n1-3:0.2.8(42)\r\n0-0:1.0.0(211227133446W)\r\n1-0:1.8.0(000123.456*kWh)\r\n1-0:1.7.0(00.500*kW)\r\n!72C0\r\n/Lux5\\SM3Xx\r\n\r\n1-3:0.2.8(42)\r\n0-0:1.0.0(211227133446W)\r\n1-0:1.8.0(000123.456*kWh)\r\n1-0:1.7.0(00.500*kW)\r\n!72C0\r\n";
+
+    #[test]
+    fn stream_from_reader() {
+        let reader = AsyncFrameReader::new(TELEGRAMS, FrameParser::default());
+
+        let frames = block_on(reader.collect::<Vec<_>>());
+
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].as_ref().unwrap().version, 42);
+    }
+
+    #[test]
+    fn stream_from_blocking_port() {
+        let port = BlockingPort::new(PortBuilder::from_data(TELEGRAMS));
+        let mut reader = AsyncFrameReader::new(port, FrameParser::default());
+
+        // The stream ends when all data of the port was read
+        let frames = block_on(reader.by_ref().collect::<Vec<_>>());
+
+        assert_eq!(frames.len(), 2);
+        assert!(frames.iter().all(|frame| frame.is_ok()));
+        assert_eq!(reader.statistics().discarded_bytes, 7);
+    }
+
+    #[test]
+    fn stream_from_file_ends() {
+        let path = std::env::temp_dir().join(format!("dsmr-stream-{}.txt", std::process::id()));
+        std::fs::write(&path, TELEGRAMS).unwrap();
+
+        let options = BufferOptions { size: 64, overflow: OverflowPolicy::Block };
+        let port = BlockingPort::with_buffer(PortBuilder::from_file(&path), options);
+        let frames = block_on(AsyncFrameReader::new(port, FrameParser::default()).collect::<Vec<_>>());
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(frames.len(), 2);
+    }
+
+    #[test]
+    fn full_buffer_drops_newest() {
+        let options = BufferOptions { size: 200, overflow: OverflowPolicy::DropNewest };
+        let port = BlockingPort::with_buffer(PortBuilder::from_data(TELEGRAMS), options);
+        let mut reader = AsyncFrameReader::new(port, FrameParser::default());
+
+        let frames = block_on(reader.by_ref().collect::<Vec<_>>());
+
+        assert_eq!(frames.len(), 1);
+        assert_eq!(reader.statistics().lost_bytes, TELEGRAMS.len() as u64 - 200);
+    }
+
+    /// Port that returns a chunk of data on every read, with the given gaps.
+    struct ChunkPort {
+        chunks: VecDeque<(&'static [u8], Vec<Gap>)>,
+        gaps: Vec<Gap>,
+    }
+
+    impl Port for ChunkPort {
+        fn fetch(&mut self) {}
+
+        fn read(&mut self) -> Option<u8> {
+            None
+        }
+
+        fn read_available(&mut self, buffer: &mut Vec<u8>) -> usize {
+            let (chunk, gaps) = self.chunks.pop_front().unwrap_or_default();
+            buffer.extend_from_slice(chunk);
+            self.gaps = gaps;
+
+            chunk.len()
+        }
+
+        fn take_gaps(&mut self) -> Vec<Gap> {
+            std::mem::take(&mut self.gaps)
+        }
+
+        fn is_closed(&self) -> bool {
+            self.chunks.is_empty()
+        }
+    }
+
+    #[test]
+    fn gaps_without_data_do_not_end_stream() {
+        // Like a decrypting port that dropped a frame it lost bytes of
+        let port = ChunkPort {
+            chunks: VecDeque::from([(&b""[..], vec![Gap { position: 0, size: 100 }]), (TELEGRAMS, vec![])]),
+            gaps: vec![],
+        };
+        let options = BufferOptions { size: 4096, overflow: OverflowPolicy::Block };
+        let mut reader = AsyncFrameReader::new(BlockingPort::with_buffer(Box::new(port), options), FrameParser::default());
+
+        let frames = block_on(reader.by_ref().collect::<Vec<_>>());
+
+        assert_eq!(frames.len(), 2);
+        assert_eq!(reader.statistics().lost_bytes, 100);
+    }
+}
@@ -0,0 +1,318 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use chrono::{DateTime, FixedOffset};
+use crate::data_frame::{DataFrame, DataFrameData};
+use crate::decimal::Decimal;
+
+/// A counter of the meter, which should never go down.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Counter {
+    DeliveredT1,
+    DeliveredT2,
+    ReceivedT1,
+    ReceivedT2,
+    Gas,
+}
+
+impl std::fmt::Display for Counter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Counter::DeliveredT1 => write!(f, "delivered T1"),
+            Counter::DeliveredT2 => write!(f, "delivered T2"),
+            Counter::ReceivedT1 => write!(f, "received T1"),
+            Counter::ReceivedT2 => write!(f, "received T2"),
+            Counter::Gas => write!(f, "gas delivered"),
+        }
+    }
+}
+
+/// Reason a frame is not plausible.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Implausibility {
+    /// A counter is lower than in the previous frame.
+    CounterDecreased { counter: Counter, previous: Decimal, current: Decimal },
+    /// An electricity counter went up more than the maximum power allows in the elapsed time.
+    CounterJump { counter: Counter, increase: Decimal, elapsed: chrono::Duration },
+    /// A counter went up so much, the increase does not fit a decimal.
+    CounterOverflow { counter: Counter, previous: Decimal, current: Decimal },
+    /// The time of the frame is before the time of the previous frame.
+    TimeWentBack { previous: DateTime<FixedOffset>, current: DateTime<FixedOffset> },
+    /// The version of the P1 output is different from the previous frame.
+    VersionChanged { previous: u32, current: u32 },
+}
+
+impl std::fmt::Display for Implausibility {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Implausibility::CounterDecreased { counter, previous, current } =>
+                write!(f, "{} went down from {} to {}", counter, previous, current),
+            Implausibility::CounterJump { counter, increase, elapsed } =>
+                write!(f, "{} went up {} in {}s", counter, increase, elapsed.num_seconds()),
+            Implausibility::CounterOverflow { counter, previous, current } =>
+                write!(f, "{} went up too much from {} to {}", counter, previous, current),
+            Implausibility::TimeWentBack { previous, current } =>
+                write!(f, "time went back from {} to {}", previous, current),
+            Implausibility::VersionChanged { previous, current } =>
+                write!(f, "version changed from {} to {}", previous, current),
+        }
+    }
+}
+
+/// What to do with frames that are not plausible.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum PlausibilityAction {
+    /// Leave the frame out.
+    Drop,
+    /// Hand the frame to the backend separately, to be reviewed.
+    Quarantine,
+    /// Store the frame as usual, with the reasons it is not plausible.
+    #[default]
+    Tag,
+}
+
+impl FromStr for PlausibilityAction {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "drop" => Ok(PlausibilityAction::Drop),
+            "quarantine" => Ok(PlausibilityAction::Quarantine),
+            "tag" => Ok(PlausibilityAction::Tag),
+            _ => Err(format!("'{}' is not one of drop, quarantine or tag", input)),
+        }
+    }
+}
+
+/// Number of implausible frames in a row after which a frame becomes the baseline anyway, for
+/// when the counters really changed, e.g. because the meter was replaced.
+const REBASELINE_AFTER: u32 = 10;
+
+/// Last plausible frame of a meter.
+#[derive(Debug)]
+struct Baseline {
+    time: DateTime<FixedOffset>,
+    counters: Vec<(Counter, Decimal)>,
+    /// Implausible frames since this frame
+    implausible_frames: u32,
+}
+
+/// Checks every frame against the previous plausible frame of the same meter.
+///
+/// Frames that are not plausible do not become the baseline, so a single corrupted frame does
+/// not cause the frames after it to be flagged too. After `REBASELINE_AFTER` implausible
+/// frames in a row, the last one becomes the baseline. The version is only flagged on the
+/// frame in which it changes.
+#[derive(Debug)]
+pub struct PlausibilityChecker {
+    max_power: f64,
+    baselines: HashMap<String, Baseline>,
+    versions: HashMap<String, u32>,
+}
+
+impl Default for PlausibilityChecker {
+    fn default() -> Self {
+        Self::new(50.0)
+    }
+}
+
+impl PlausibilityChecker {
+    /// Create a checker for meters with a connection of at most `max_power` kW.
+    pub fn new(max_power: f64) -> Self {
+        Self {
+            max_power,
+            baselines: HashMap::new(),
+            versions: HashMap::new(),
+        }
+    }
+
+    /// Check a frame. An empty list means the frame is plausible.
+    pub fn check(&mut self, data_frame: &DataFrame) -> Vec<Implausibility> {
+        let meter = data_frame.meter_identity();
+        let mut problems = Vec::new();
+
+        if let Some(previous) = self.versions.insert(meter.clone(), data_frame.version) {
+            if previous != data_frame.version {
+                problems.push(Implausibility::VersionChanged { previous, current: data_frame.version });
+            }
+        }
+
+        let counters = counters(&data_frame.data);
+
+        if let Some(baseline) = self.baselines.get(&meter) {
+            let elapsed = data_frame.time - baseline.time;
+            if elapsed < chrono::Duration::zero() {
+                problems.push(Implausibility::TimeWentBack { previous: baseline.time, current: data_frame.time });
+            }
+
+            for (counter, current) in &counters {
+                let previous = match baseline.counters.iter().find(|(c, _)| c == counter) {
+                    Some((_, previous)) => *previous,
+                    None => continue,
+                };
+
+                if *current < previous {
+                    problems.push(Implausibility::CounterDecreased { counter: *counter, previous, current: *current });
+                } else if *counter != Counter::Gas && elapsed >= chrono::Duration::zero() {
+                    let increase = match current.checked_sub(previous) {
+                        Some(increase) => increase,
+                        None => {
+                            problems.push(Implausibility::CounterOverflow { counter: *counter, previous, current: *current });
+                            continue;
+                        }
+                    };
+
+                    // Allow for the counter being rounded to its last digit
+                    let maximum = self.max_power * elapsed.num_milliseconds() as f64 / 3_600_000.0
+                        + Decimal::new(1, current.scale()).to_f64();
+
+                    if increase.to_f64() > maximum {
+                        problems.push(Implausibility::CounterJump { counter: *counter, increase, elapsed });
+                    }
+                }
+            }
+        }
+
+        let plausible = problems.iter().all(|p| matches!(p, Implausibility::VersionChanged { .. }));
+        match self.baselines.get_mut(&meter) {
+            Some(baseline) if !plausible && baseline.implausible_frames + 1 < REBASELINE_AFTER => {
+                baseline.implausible_frames += 1;
+            }
+            _ => {
+                self.baselines.insert(meter, Baseline { time: data_frame.time, counters, implausible_frames: 0 });
+            }
+        }
+
+        problems
+    }
+}
+
+fn counters(data: &DataFrameData) -> Vec<(Counter, Decimal)> {
+    let mut counters = vec![
+        (Counter::DeliveredT1, data.electricity_delivered_t1),
+        (Counter::DeliveredT2, data.electricity_delivered_t2),
+        (Counter::ReceivedT1, data.electricity_received_t1),
+        (Counter::ReceivedT2, data.electricity_received_t2),
+    ];
+
+    // Frames without a gas reading have a gas counter of 0, which is not a reading
+    let gas = data.mbus_devices.iter().find(|d| d.is_gas()).and_then(|d| d.reading.as_ref());
+    if gas.is_some() {
+        counters.push((Counter::Gas, data.gas_delivered));
+    }
+
+    counters
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, FixedOffset, TimeZone};
+    use crate::data_frame::{DataFrame, Measurement, Object, TelegramObject, Unit};
+    use crate::decimal::Decimal;
+    use crate::obis::ObisCode;
+    use crate::validation::{Counter, Implausibility, PlausibilityChecker, REBASELINE_AFTER};
+
+    fn frame(seconds: i64, delivered: &str, version: u32) -> DataFrame {
+        let time = FixedOffset::east_opt(3600).unwrap().with_ymd_and_hms(2021, 12, 27, 12, 0, 0).unwrap() + Duration::seconds(seconds);
+        let delivered = Measurement::new(Decimal::parse(delivered).unwrap(), Unit::KWh);
+        let objects = vec![
+            TelegramObject::new(ObisCode::new(1, 3, 0, 2, 8), Object::Version(version)),
+            TelegramObject::new(ObisCode::new(0, 0, 1, 0, 0), Object::Time(time)),
+            TelegramObject::new(ObisCode::new(1, 0, 1, 8, 1), Object::ElectricityDeliveredT1(delivered)),
+        ];
+
+        DataFrame::new(String::from("ISK"), String::new(), objects, None, 0)
+    }
+
+    #[test]
+    fn plausible_frames() {
+        let mut checker = PlausibilityChecker::default();
+
+        assert_eq!(checker.check(&frame(0, "100.000", 50)), vec![]);
+        assert_eq!(checker.check(&frame(10, "100.010", 50)), vec![]);
+        assert_eq!(checker.check(&frame(10, "100.010", 50)), vec![]);
+    }
+
+    #[test]
+    fn counter_going_down() {
+        let mut checker = PlausibilityChecker::default();
+        checker.check(&frame(0, "100.000", 50));
+
+        assert_eq!(checker.check(&frame(10, "99.000", 50)), vec![Implausibility::CounterDecreased {
+            counter: Counter::DeliveredT1,
+            previous: Decimal::new(100000, 3),
+            current: Decimal::new(99000, 3),
+        }]);
+        // The corrupted frame is not the baseline of the next one
+        assert_eq!(checker.check(&frame(20, "100.001", 50)), vec![]);
+    }
+
+    #[test]
+    fn rebaseline_after_implausible_frames() {
+        let mut checker = PlausibilityChecker::default();
+        checker.check(&frame(0, "100.000", 50));
+
+        // The meter was replaced by one with lower counters
+        for i in 1..REBASELINE_AFTER as i64 {
+            assert_eq!(checker.check(&frame(i * 10, "5.000", 50)).len(), 1);
+        }
+        assert_eq!(checker.check(&frame(100, "5.000", 50)).len(), 1);
+        assert_eq!(checker.check(&frame(110, "5.001", 50)), vec![]);
+    }
+
+    #[test]
+    fn impossible_jump() {
+        let mut checker = PlausibilityChecker::new(10.0);
+        checker.check(&frame(0, "100.000", 50));
+
+        // 10 kW for an hour is 10 kWh
+        assert_eq!(checker.check(&frame(3600, "110.000", 50)), vec![]);
+        assert_eq!(checker.check(&frame(3610, "111.000", 50)), vec![Implausibility::CounterJump {
+            counter: Counter::DeliveredT1,
+            increase: Decimal::new(1000, 3),
+            elapsed: Duration::seconds(10),
+        }]);
+    }
+
+    #[test]
+    fn increase_that_does_not_fit() {
+        let mut checker = PlausibilityChecker::default();
+        checker.check(&frame(0, "1.000000000", 50));
+
+        assert_eq!(checker.check(&frame(10, "9999999999", 50)), vec![Implausibility::CounterOverflow {
+            counter: Counter::DeliveredT1,
+            previous: Decimal::new(1, 0),
+            current: Decimal::new(9999999999, 0),
+        }]);
+    }
+
+    #[test]
+    fn time_going_back() {
+        let mut checker = PlausibilityChecker::default();
+        checker.check(&frame(10, "100.000", 50));
+
+        let problems = checker.check(&frame(0, "100.000", 50));
+
+        assert!(matches!(problems[..], [Implausibility::TimeWentBack { .. }]));
+    }
+
+    #[test]
+    fn version_change_is_flagged_once() {
+        let mut checker = PlausibilityChecker::default();
+        checker.check(&frame(0, "100.000", 42));
+
+        assert_eq!(checker.check(&frame(10, "100.000", 50)), vec![Implausibility::VersionChanged { previous: 42, current: 50 }]);
+        assert_eq!(checker.check(&frame(20, "100.000", 50)), vec![]);
+    }
+
+    #[test]
+    fn meters_are_checked_separately() {
+        let mut checker = PlausibilityChecker::default();
+        checker.check(&frame(0, "100.000", 50));
+
+        let other = DataFrame::new(String::from("KFM"), String::from("other"), vec![
+            TelegramObject::new(ObisCode::new(1, 0, 1, 8, 1), Object::ElectricityDeliveredT1(Measurement::new(Decimal::new(5, 0), Unit::KWh))),
+        ], None, 0);
+
+        assert!(checker.check(&other).is_empty());
+    }
+}